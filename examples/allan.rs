@@ -1,6 +1,9 @@
-#![cfg_attr(feature = "cargo-clippy", deny(warnings))]
+#![cfg_attr(clippy, deny(warnings))]
 #![allow(unknown_lints)]
-#![allow(many_single_char_names)]
+#![allow(clippy::many_single_char_names)]
+// the example predates these lints
+#![allow(clippy::needless_late_init, clippy::print_literal)]
+#![allow(clippy::redundant_locals, clippy::unnecessary_cast)]
 extern crate getopts;
 #[macro_use]
 extern crate log;
@@ -10,7 +13,8 @@ extern crate time;
 use getopts::Options;
 use log::{LogLevel, LogLevelFilter, LogMetadata, LogRecord};
 use std::{env, fmt, thread};
-use tic::{Clocksource, HttpReporter, Interest, Percentile, Receiver, Sample, Sender};
+use tic::{Clocksource, HttpReporter, Interest, Percentile, Receiver, Sample, Sender,
+          WaterfallOptions};
 use tic::SECOND;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -152,6 +156,7 @@ fn main() {
     receiver.add_interest(Interest::LatencyWaterfall(
         Metric::Ok,
         "ok_waterfall.png".to_owned(),
+        WaterfallOptions::default(),
     ));
     receiver.add_interest(Interest::LatencyTrace(
         Metric::Ok,
@@ -219,6 +224,8 @@ fn main() {
         }
     }
     info!("saving files...");
-    receiver.save_files();
-    info!("saved");
+    match receiver.save_files() {
+        Ok(()) => info!("saved"),
+        Err(errors) => error!("failed to save {} files", errors.len()),
+    }
 }
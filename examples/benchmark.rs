@@ -1,4 +1,6 @@
-#![cfg_attr(feature = "cargo-clippy", deny(warnings))]
+#![cfg_attr(clippy, deny(warnings))]
+// the example predates these lints
+#![allow(clippy::needless_late_init, clippy::print_literal)]
 extern crate getopts;
 #[macro_use]
 extern crate log;
@@ -10,7 +12,8 @@ use log::{LogLevel, LogLevelFilter, LogMetadata, LogRecord};
 use std::env;
 use std::fmt;
 use std::thread;
-use tic::{Clocksource, HttpReporter, Interest, Percentile, Receiver, Sample, Sender};
+use tic::{Clocksource, HttpReporter, Interest, Percentile, Receiver, Sample, Scale, Sender,
+          WaterfallOptions};
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Metric {
@@ -181,13 +184,22 @@ fn main() {
     receiver.add_interest(Interest::LatencyWaterfall(
         Metric::Ok,
         "ok_waterfall.png".to_owned(),
+        WaterfallOptions::default()
+            .scale(Scale::Log)
+            .percentile(50.0)
+            .percentile(99.0),
     ));
     receiver.add_interest(Interest::LatencyTrace(
         Metric::Ok,
         "ok_trace.txt".to_owned(),
     ));
+    receiver.add_interest(Interest::LatencySeries(
+        Metric::Ok,
+        "ok_series.csv".to_owned(),
+    ));
     receiver.add_interest(Interest::Count(Metric::Ok));
     receiver.add_interest(Interest::LatencyPercentile(Metric::Ok));
+    receiver.add_interest(Interest::LatencyPercentileCumulative(Metric::Ok));
     receiver.add_interest(Interest::Count(Metric::Total));
     receiver.add_interest(Interest::Gauge(Metric::Total));
 
@@ -206,13 +218,8 @@ fn main() {
 
     let mut total = 0;
 
-    let windows = windows;
-    // we run the receiver manually so we can access the Meters
-    for _ in 0..windows {
-        let t0 = clocksource.time();
-        receiver.run_once();
-        let t1 = clocksource.time();
-        let m = receiver.clone_meters();
+    // print the rate and latencies as each window closes
+    receiver.run_with(|window, m| {
         let mut int = 0;
         if let Some(t) = m.count(&Metric::Ok) {
             int += *t;
@@ -224,7 +231,7 @@ fn main() {
 
         let c = int - total;
         total = int;
-        let r = c as f64 / ((t1 - t0) as f64 / 1_000_000_000.0);
+        let r = c as f64 / ((window.end - window.start) as f64 / 1_000_000_000.0);
 
         info!("rate: {} samples per second", r);
         info!(
@@ -240,21 +247,23 @@ fn main() {
             m.latency_percentile(&Metric::Ok, Percentile("max".to_owned(), 100.0))
                 .unwrap_or(&0)
         );
-    }
-
-    let m = receiver.clone_meters();
-    let mut c = 0;
-    if let Some(t) = m.count(&Metric::Ok) {
-        c += *t;
-    }
-
-    if let Some(t) = m.count(&Metric::Total) {
-        c += *t;
-    }
+    });
 
-    info!("total metrics pushed: {}", c);
+    info!("total metrics pushed: {}", total);
 
-    info!("saving files...");
-    receiver.save_files();
-    info!("saved");
+    // print the latencies over the whole run
+    let m = receiver.clone_meters();
+    info!(
+        "run latency (ns): p50: {} p90: {} p999: {} p9999: {} max: {}",
+        m.latency_cumulative(&Metric::Ok, Percentile("p50".to_owned(), 50.0))
+            .unwrap_or(&0),
+        m.latency_cumulative(&Metric::Ok, Percentile("p90".to_owned(), 90.0))
+            .unwrap_or(&0),
+        m.latency_cumulative(&Metric::Ok, Percentile("p999".to_owned(), 99.9))
+            .unwrap_or(&0),
+        m.latency_cumulative(&Metric::Ok, Percentile("p9999".to_owned(), 99.99))
+            .unwrap_or(&0),
+        m.latency_cumulative(&Metric::Ok, Percentile("max".to_owned(), 100.0))
+            .unwrap_or(&0)
+    );
 }
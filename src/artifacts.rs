@@ -0,0 +1,238 @@
+// `Artifacts` decide where traces and waterfalls are saved, and remove old
+// ones once the retention count is exceeded
+
+use fnv::FnvHashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use time;
+
+/// an `ArtifactError` describes a trace or waterfall which failed to save
+#[derive(Debug)]
+pub struct ArtifactError {
+    /// the file name template of the artifact
+    pub template: String,
+    /// the path the artifact was to be saved to
+    pub path: PathBuf,
+    /// the error which stopped the artifact from being saved
+    pub error: io::Error,
+}
+
+impl ArtifactError {
+    pub(crate) fn new(template: String, path: PathBuf, error: io::Error) -> ArtifactError {
+        ArtifactError {
+            template: template,
+            path: path,
+            error: error,
+        }
+    }
+}
+
+impl fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed to save {} as {}: {}",
+            self.template,
+            self.path.display(),
+            self.error
+        )
+    }
+}
+
+/// the values substituted into artifact filename templates
+pub struct Placeholders {
+    /// the UTC time the artifact was saved
+    pub date: String,
+    /// the number of windows which closed before the artifact was saved
+    pub window: usize,
+    /// the number of runs which completed before this one
+    pub run: usize,
+}
+
+impl Placeholders {
+    pub fn now(window: usize, run: usize) -> Placeholders {
+        Placeholders {
+            date: time::strftime("%Y%m%dT%H%M%SZ", &time::now_utc()).unwrap_or_default(),
+            window: window,
+            run: run,
+        }
+    }
+}
+
+/// expand the `{date}`, `{window}` and `{run}` placeholders of a template
+pub fn expand(template: &str, placeholders: &Placeholders) -> String {
+    template
+        .replace("{date}", &placeholders.date)
+        .replace("{window}", &placeholders.window.to_string())
+        .replace("{run}", &placeholders.run.to_string())
+}
+
+/// the template for the artifact of one metric, from a template shared by all
+/// metrics. the `{metric}` placeholder is replaced with the name of the
+/// metric, if there is none the name is added before the extension
+pub fn for_metric(template: &str, metric: &str) -> String {
+    if template.contains("{metric}") {
+        return template.replace("{metric}", metric);
+    }
+    let path = Path::new(template);
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return format!("{}{}", template, metric),
+    };
+    let name = match name.rfind('.') {
+        Some(i) if i > 0 => format!("{}_{}{}", &name[..i], metric, &name[i..]),
+        _ => format!("{}_{}", name, metric),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+pub struct Artifacts {
+    directory: Option<PathBuf>,
+    retention: usize,
+    saved: FnvHashMap<String, VecDeque<PathBuf>>,
+}
+
+impl Artifacts {
+    pub fn new(directory: Option<PathBuf>, retention: usize) -> Artifacts {
+        Artifacts {
+            directory: directory,
+            retention: retention,
+            saved: FnvHashMap::default(),
+        }
+    }
+
+    /// the path an artifact with the given template is saved to
+    pub fn path(&self, template: &str, placeholders: &Placeholders) -> PathBuf {
+        let file = expand(template, placeholders);
+        match self.directory {
+            Some(ref directory) => directory.join(file),
+            None => PathBuf::from(file),
+        }
+    }
+
+    /// save an artifact by writing it to a temporary file next to the path,
+    /// which is renamed into place once complete. once more than the
+    /// retention count of artifacts have been saved for the template, the
+    /// oldest are removed
+    pub fn save<F>(&mut self, template: &str, path: PathBuf, write: F) -> Result<(), io::Error>
+    where
+        F: FnOnce(String) -> Result<(), io::Error>,
+    {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let temp = temp_path(&path);
+        if let Err(e) = write(temp.to_string_lossy().into_owned()).and_then(|_| {
+            fs::rename(&temp, &path)
+        })
+        {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+
+        let saved = self.saved
+            .entry(template.to_owned())
+            .or_default();
+        if !saved.contains(&path) {
+            saved.push_back(path);
+        }
+        while self.retention > 0 && saved.len() > self.retention {
+            if let Some(old) = saved.pop_front() {
+                if let Err(e) = fs::remove_file(&old) {
+                    debug!("failed to remove old artifact {}: {}", old.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// a hidden file in the same directory, which keeps the extension so the
+// renderer writes the right format
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".tmp_{}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::process;
+
+    fn placeholders(window: usize) -> Placeholders {
+        Placeholders {
+            date: "20170101T000000Z".to_owned(),
+            window: window,
+            run: 2,
+        }
+    }
+
+    #[test]
+    fn expansion() {
+        assert_eq!(
+            expand("ok_{date}_{window}_{run}.png", &placeholders(60)),
+            "ok_20170101T000000Z_60_2.png"
+        );
+        assert_eq!(expand("ok.png", &placeholders(60)), "ok.png");
+    }
+
+    #[test]
+    fn metric_templates() {
+        assert_eq!(for_metric("/tmp/{metric}.png", "ok"), "/tmp/ok.png");
+        assert_eq!(for_metric("/tmp/waterfall.png", "ok"), "/tmp/waterfall_ok.png");
+        assert_eq!(for_metric("heatmap.trace", "ok"), "heatmap_ok.trace");
+        assert_eq!(for_metric("trace", "ok"), "trace_ok");
+    }
+
+    #[test]
+    fn retention() {
+        let directory = env::temp_dir().join(format!("tic_artifacts_{}", process::id()));
+        let mut artifacts = Artifacts::new(Some(directory.clone()), 2);
+        let template = "ok_{window}.txt";
+
+        let mut paths = Vec::new();
+        for window in 0..3 {
+            let path = artifacts.path(template, &placeholders(window));
+            assert_eq!(path, directory.join(format!("ok_{}.txt", window)));
+            artifacts
+                .save(template, path.clone(), |temp| {
+                    assert!(temp.ends_with(&format!(".tmp_ok_{}.txt", window)));
+                    File::create(temp)?.write_all(b"ok")
+                })
+                .unwrap();
+            paths.push(path);
+        }
+
+        assert!(!paths[0].exists());
+        assert!(paths[1].exists());
+        assert!(paths[2].exists());
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 2);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn missing_artifact() {
+        let directory = env::temp_dir().join(format!("tic_missing_{}", process::id()));
+        let mut artifacts = Artifacts::new(Some(directory.clone()), 0);
+        let path = artifacts.path("ok.txt", &placeholders(0));
+        assert!(artifacts.save("ok.txt", path.clone(), |_| Ok(())).is_err());
+        let error = artifacts
+            .save("ok.txt", path.clone(), |_| {
+                Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+            })
+            .unwrap_err();
+        assert_eq!(format!("{}", error), "disk full");
+        assert!(!path.exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+#![cfg_attr(clippy, deny(warnings))]
+extern crate getopts;
+extern crate tic;
+
+use getopts::{Matches, Options};
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use tic::{Palette, Scale, Trace, WaterfallOptions};
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} [options] TRACE", program);
+    print!("{}", opts.usage(&brief));
+}
+
+pub fn opts() -> Options {
+    let mut opts = Options::new();
+
+    opts.optopt(
+        "o",
+        "output",
+        "file to render, a .png waterfall or a .svg or .html heatmap",
+        "FILE",
+    );
+    opts.optopt("", "start", "crop to slices after this many seconds", "INTEGER");
+    opts.optopt("", "end", "crop to slices before this many seconds", "INTEGER");
+    opts.optopt("", "min", "crop to values of at least this many ns", "INTEGER");
+    opts.optopt("", "max", "crop to values of at most this many ns", "INTEGER");
+    opts.optopt("", "scale", "intensity scale: linear or log", "SCALE");
+    opts.optopt(
+        "",
+        "palette",
+        "colors: classic, grayscale or heat",
+        "PALETTE",
+    );
+    opts.optmulti(
+        "p",
+        "percentile",
+        "percentile to overlay or print, may be repeated",
+        "FLOAT",
+    );
+    opts.optflag(
+        "s",
+        "series",
+        "print the percentiles of each slice as CSV instead of rendering",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    opts
+}
+
+// exit with the message if the argument is not valid
+fn fail(message: &str) -> ! {
+    eprintln!("tic-render: {}", message);
+    process::exit(1);
+}
+
+fn number<T: FromStr>(matches: &Matches, name: &str) -> Option<T> {
+    matches.opt_str(name).map(|value| match value.parse() {
+        Ok(value) => value,
+        Err(_) => fail(&format!("invalid --{}: {}", name, value)),
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let program = &args[0];
+
+    let opts = opts();
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => fail(&format!("failed to parse command line args: {}", f)),
+    };
+
+    if matches.opt_present("help") || matches.free.len() != 1 {
+        print_usage(program, &opts);
+        return;
+    }
+
+    let mut trace = match Trace::load(&matches.free[0]) {
+        Ok(trace) => trace,
+        Err(e) => fail(&format!("failed to load {}: {}", matches.free[0], e)),
+    };
+    let start = number(&matches, "start");
+    let end = number(&matches, "end");
+    if start.is_some() || end.is_some() {
+        trace = trace.crop_time(start.unwrap_or(0), end.unwrap_or_else(u64::max_value));
+    }
+    let min = number(&matches, "min");
+    let max = number(&matches, "max");
+    if min.is_some() || max.is_some() {
+        trace = trace.crop_values(min.unwrap_or(0), max.unwrap_or_else(u64::max_value));
+    }
+
+    let percentiles: Vec<f64> = matches
+        .opt_strs("percentile")
+        .iter()
+        .map(|p| match p.parse::<f64>() {
+            Ok(p) if (0.0..=100.0).contains(&p) => p,
+            _ => fail(&format!("invalid --percentile: {}", p)),
+        })
+        .collect();
+
+    if matches.opt_present("series") {
+        let percentiles = if percentiles.is_empty() {
+            vec![50.0, 90.0, 99.0, 100.0]
+        } else {
+            percentiles
+        };
+        let header: Vec<String> = percentiles.iter().map(|p| format!("p{}", p)).collect();
+        println!("timestamp,{}", header.join(","));
+        for (start, values) in trace.percentiles(&percentiles) {
+            let values: Vec<String> = values
+                .iter()
+                .map(|v| v.map(|v| v.to_string()).unwrap_or_default())
+                .collect();
+            println!("{},{}", start, values.join(","));
+        }
+        return;
+    }
+
+    let mut options = WaterfallOptions::default();
+    match matches.opt_str("scale").as_deref() {
+        None | Some("linear") => {}
+        Some("log") => options = options.scale(Scale::Log),
+        Some(other) => fail(&format!("unknown --scale: {}", other)),
+    }
+    match matches.opt_str("palette").as_deref() {
+        None | Some("classic") => {}
+        Some("grayscale") => options = options.palette(Palette::Grayscale),
+        Some("heat") => options = options.palette(Palette::Heat),
+        Some(other) => fail(&format!("unknown --palette: {}", other)),
+    }
+    for percentile in percentiles {
+        options = options.percentile(percentile);
+    }
+
+    let output = matches
+        .opt_str("output")
+        .unwrap_or_else(|| "waterfall.png".to_owned());
+    let contents = match Path::new(&output).extension().and_then(|e| e.to_str()) {
+        Some("png") => match trace.waterfall(&options) {
+            Ok(png) => png,
+            Err(e) => fail(&format!("failed to render {}: {}", output, e)),
+        },
+        Some("svg") => trace.svg(&options).into_bytes(),
+        Some("html") => trace.html(&options, &matches.free[0]).into_bytes(),
+        _ => fail(&format!("unknown output format: {}", output)),
+    };
+    if let Err(e) = File::create(&output).and_then(|mut f| f.write_all(&contents)) {
+        fail(&format!("failed to write {}: {}", output, e));
+    }
+}
@@ -0,0 +1,338 @@
+// `Buckets` hold the raw latency distributions of metrics, which can be
+// serialized and merged across processes
+
+use histogram::Histogram;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io;
+
+const MAGIC: &[u8; 4] = b"TICB";
+const VERSION: u8 = 1;
+
+/// `Buckets` are the non-empty buckets of the latency histograms of each
+/// metric, keyed by the `Display` of the metric
+///
+/// a `Receiver` exports the lifetime histograms of the metrics with a
+/// `LatencyPercentileCumulative` interest, so the `Buckets` cover the whole
+/// run rather than the last window
+///
+/// unlike percentiles, `Buckets` from several processes can be merged into
+/// one distribution. they are keyed by name rather than by the metric type of
+/// a `Receiver`, so the processes need not share a metric type
+///
+/// they are serialized in a compact binary format: the magic `TICB` and a
+/// version byte, followed by varint encoded metric names, bucket values as
+/// deltas, and counts
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Buckets {
+    data: BTreeMap<String, BTreeMap<u64, u64>>,
+}
+
+impl Buckets {
+    /// create a new empty set of `Buckets`
+    pub fn new() -> Buckets {
+        Default::default()
+    }
+
+    // add the non-empty buckets of the histogram to the named metric, the
+    // counts saturate as those of the histogram do
+    pub(crate) fn add_histogram(&mut self, metric: &str, histogram: &Histogram) {
+        let counts = self.data.entry(metric.to_owned()).or_default();
+        for bucket in histogram {
+            if bucket.count() > 0 {
+                let total = counts.entry(bucket.value()).or_insert(0);
+                *total = total.saturating_add(bucket.count());
+            }
+        }
+    }
+
+    /// add the count to the bucket of the given value for the named metric,
+    /// an error is returned if the count of the bucket would overflow
+    pub fn increment_by(&mut self, metric: &str, value: u64, count: u64) -> Result<(), io::Error> {
+        let counts = self.data.entry(metric.to_owned()).or_default();
+        add(counts, value, count)
+    }
+
+    /// the names of the metrics, in sorted order
+    pub fn metrics(&self) -> Vec<&str> {
+        self.data.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// the value and count of each non-empty bucket for the named metric, in
+    /// ascending order of value
+    pub fn buckets(&self, metric: &str) -> Vec<(u64, u64)> {
+        match self.data.get(metric) {
+            Some(counts) => counts.iter().map(|(v, c)| (*v, *c)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// the total count of the named metric
+    pub fn entries(&self, metric: &str) -> u64 {
+        match self.data.get(metric) {
+            Some(counts) => total(counts),
+            None => 0,
+        }
+    }
+
+    /// the value at the given percentile for the named metric, calculated in
+    /// the same way as the percentiles of a `Receiver`
+    pub fn percentile(&self, metric: &str, percentile: f64) -> Option<u64> {
+        let counts = self.data.get(metric)?;
+        let entries = total(counts);
+        if entries == 0 || !(0.0..=100.0).contains(&percentile) {
+            return None;
+        }
+        let rank = cmp::min(
+            entries,
+            ((percentile / 100.0) * entries as f64).ceil() as u64,
+        );
+        // like the histograms, percentiles above the median are counted
+        // down from the maximum
+        let need = if percentile < 50.0 {
+            cmp::max(1, rank)
+        } else {
+            entries + 1 - cmp::max(1, entries - rank)
+        };
+        let mut seen: u64 = 0;
+        for (value, count) in counts {
+            seen = seen.saturating_add(*count);
+            if seen >= need {
+                return Some(*value);
+            }
+        }
+        None
+    }
+
+    /// add all the buckets of the other `Buckets` to these. if the count of
+    /// any bucket would overflow an error is returned, and these `Buckets` are
+    /// unchanged
+    pub fn merge(&mut self, other: &Buckets) -> Result<(), io::Error> {
+        for (metric, counts) in &other.data {
+            if let Some(existing) = self.data.get(metric) {
+                for (value, count) in counts {
+                    let total = existing.get(value).cloned().unwrap_or(0);
+                    if total.checked_add(*count).is_none() {
+                        return Err(overflow());
+                    }
+                }
+            }
+        }
+        for (metric, counts) in &other.data {
+            for (value, count) in counts {
+                self.increment_by(metric, *value, *count)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// merge the serialized `Buckets` from several processes into one
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Buckets;
+    /// let mut a = Buckets::new();
+    /// a.increment_by("ok", 100, 1).unwrap();
+    /// let mut b = Buckets::new();
+    /// b.increment_by("ok", 200, 1).unwrap();
+    ///
+    /// let merged = Buckets::merge_all(&[a.serialize(), b.serialize()]).unwrap();
+    /// assert_eq!(merged.entries("ok"), 2);
+    /// ```
+    pub fn merge_all<B: AsRef<[u8]>>(inputs: &[B]) -> Result<Buckets, io::Error> {
+        let mut merged = Buckets::new();
+        for input in inputs {
+            merged.merge(&Buckets::deserialize(input.as_ref())?)?;
+        }
+        Ok(merged)
+    }
+
+    /// serialize the `Buckets` in the compact binary format
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut output = MAGIC.to_vec();
+        output.push(VERSION);
+        put_varint(&mut output, self.data.len() as u64);
+        for (metric, counts) in &self.data {
+            put_varint(&mut output, metric.len() as u64);
+            output.extend_from_slice(metric.as_bytes());
+            put_varint(&mut output, counts.len() as u64);
+            let mut previous = 0;
+            for (value, count) in counts {
+                put_varint(&mut output, value - previous);
+                put_varint(&mut output, *count);
+                previous = *value;
+            }
+        }
+        output
+    }
+
+    /// deserialize `Buckets` from the compact binary format
+    pub fn deserialize(input: &[u8]) -> Result<Buckets, io::Error> {
+        if input.len() < 5 || &input[..4] != MAGIC {
+            return Err(invalid("not serialized buckets"));
+        }
+        if input[4] != VERSION {
+            return Err(invalid("unsupported buckets version"));
+        }
+        let mut buckets = Buckets::new();
+        let mut position = 5;
+        let metrics = get_varint(input, &mut position)?;
+        for _ in 0..metrics {
+            let len = get_varint(input, &mut position)? as usize;
+            if input.len() - position < len {
+                return Err(invalid("truncated metric name"));
+            }
+            let metric = String::from_utf8(input[position..(position + len)].to_vec())
+                .map_err(|_| invalid("metric name is not utf8"))?;
+            position += len;
+            let counts = buckets.data.entry(metric).or_default();
+            let mut value: u64 = 0;
+            for _ in 0..get_varint(input, &mut position)? {
+                value = value
+                    .checked_add(get_varint(input, &mut position)?)
+                    .ok_or_else(|| invalid("bucket value overflow"))?;
+                add(counts, value, get_varint(input, &mut position)?)?;
+            }
+        }
+        if position != input.len() {
+            return Err(invalid("trailing bytes after buckets"));
+        }
+        Ok(buckets)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn overflow() -> io::Error {
+    invalid("bucket count overflow")
+}
+
+// add the count to the bucket of the given value
+fn add(counts: &mut BTreeMap<u64, u64>, value: u64, count: u64) -> Result<(), io::Error> {
+    let total = counts.entry(value).or_insert(0);
+    *total = total.checked_add(count).ok_or_else(overflow)?;
+    Ok(())
+}
+
+// the total count of the buckets, saturating at the max value
+fn total(counts: &BTreeMap<u64, u64>) -> u64 {
+    counts.values().fold(0, |total, count| total.saturating_add(*count))
+}
+
+// append an unsigned LEB128 varint
+fn put_varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+// read an unsigned LEB128 varint, advancing the position
+fn get_varint(input: &[u8], position: &mut usize) -> Result<u64, io::Error> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        if *position >= input.len() {
+            return Err(invalid("truncated varint"));
+        }
+        if shift > 63 {
+            return Err(invalid("varint overflow"));
+        }
+        let byte = input[*position];
+        *position += 1;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints() {
+        for value in &[0, 1, 127, 128, 300, u64::MAX] {
+            let mut output = Vec::new();
+            put_varint(&mut output, *value);
+            let mut position = 0;
+            assert_eq!(get_varint(&output, &mut position).unwrap(), *value);
+            assert_eq!(position, output.len());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut histogram = Histogram::new();
+        for value in 100..200 {
+            histogram.increment(value).unwrap();
+        }
+        let mut buckets = Buckets::new();
+        buckets.add_histogram("ok", &histogram);
+        buckets.increment_by("total", 1_000_000_000, 3).unwrap();
+
+        let decoded = Buckets::deserialize(&buckets.serialize()).unwrap();
+        assert_eq!(decoded, buckets);
+        assert_eq!(decoded.metrics(), vec!["ok", "total"]);
+        assert_eq!(decoded.entries("ok"), 100);
+        assert_eq!(decoded.percentile("ok", 50.0), histogram.percentile(50.0).ok());
+        assert_eq!(decoded.buckets("total"), vec![(1_000_000_000, 3)]);
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = Buckets::new();
+        a.increment_by("ok", 100, 3).unwrap();
+        a.increment_by("ok", 200, 1).unwrap();
+        let mut b = Buckets::new();
+        b.increment_by("ok", 200, 1).unwrap();
+        b.increment_by("ok", 400, 5).unwrap();
+        b.increment_by("other", 1, 1).unwrap();
+
+        let merged = Buckets::merge_all(&[a.serialize(), b.serialize()]).unwrap();
+        assert_eq!(merged.buckets("ok"), vec![(100, 3), (200, 2), (400, 5)]);
+        assert_eq!(merged.entries("ok"), 10);
+        assert_eq!(merged.percentile("ok", 30.0), Some(100));
+        assert_eq!(merged.percentile("ok", 40.0), Some(200));
+        assert_eq!(merged.percentile("ok", 50.0), Some(400));
+        assert_eq!(merged.percentile("ok", 100.0), Some(400));
+        assert_eq!(merged.entries("other"), 1);
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(Buckets::deserialize(b"").is_err());
+        assert!(Buckets::deserialize(b"TICX\x01\x00").is_err());
+        assert!(Buckets::deserialize(b"TICB\x02\x00").is_err());
+        assert!(Buckets::deserialize(b"TICB\x01\x01\x05ok").is_err());
+        assert!(Buckets::deserialize(b"TICB\x01\x00\x00").is_err());
+        assert_eq!(Buckets::deserialize(b"TICB\x01\x00").unwrap(), Buckets::new());
+    }
+
+    #[test]
+    fn overflow() {
+        let mut a = Buckets::new();
+        a.increment_by("ok", 100, u64::MAX).unwrap();
+        assert!(a.increment_by("ok", 100, 1).is_err());
+        assert_eq!(a.entries("ok"), u64::MAX);
+
+        let mut b = Buckets::new();
+        b.increment_by("ok", 200, 1).unwrap();
+        b.increment_by("ok", 100, 1).unwrap();
+        let mut merged = a.clone();
+        assert!(merged.merge(&b).is_err());
+        assert_eq!(merged, a);
+        assert!(Buckets::merge_all(&[a.serialize(), b.serialize()]).is_err());
+
+        // a serialized metric which holds the same bucket twice
+        let mut input = b"TICB\x01\x01\x02ok\x02\x05".to_vec();
+        put_varint(&mut input, u64::MAX);
+        input.extend_from_slice(b"\x00\x01");
+        assert!(Buckets::deserialize(&input).is_err());
+    }
+}
@@ -1,5 +1,9 @@
+use buckets::Buckets;
 use data::Meters;
+use render::WaterfallOptions;
+use std::io;
 use std::sync::mpsc::SyncSender;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Duration of 1 second in nanoseconds
 pub const SECOND: u64 = 1_000_000_000;
@@ -8,6 +12,9 @@ pub const MINUTE: u64 = 60 * SECOND;
 
 #[derive(Clone, Eq, Hash, PartialEq)]
 /// an Interest registers a metric for reporting
+///
+/// the file names of trace and waterfall interests are templates which may
+/// contain placeholders, see `Config::artifact_directory`
 pub enum Interest<T> {
     /// Calculate ADEV for the given metric based on the phase difference
     /// between start and stop of each `Sample`. NOTE: It is expected that there
@@ -21,6 +28,11 @@ pub enum Interest<T> {
     /// Calculate latency percentiles for metric based on the delta between
     /// start and stop time for each `Sample`
     LatencyPercentile(T),
+    /// Calculate latency percentiles for metric over the lifetime of the
+    /// `Receiver`, based on the delta between start and stop time for each
+    /// `Sample`. The histogram is never cleared, its raw buckets are exported
+    /// by `Controller::get_buckets`
+    LatencyPercentileCumulative(T),
     /// Calculate value percentiles for metric based on the counts associated
     /// with each `Sample`
     ValuePercentile(T),
@@ -28,14 +40,34 @@ pub enum Interest<T> {
     /// between start and stop time for each `Sample`
     LatencyTrace(T, String),
     /// Generate a PNG plot of the latency heatmaps which store the delta
-    /// between start and stop time for each `Sample`
-    LatencyWaterfall(T, String),
+    /// between start and stop time for each `Sample`, rendered with the given
+    /// options
+    LatencyWaterfall(T, String, WaterfallOptions),
+    /// Generate an SVG plot of the latency heatmaps which store the delta
+    /// between start and stop time for each `Sample`, with a tooltip showing
+    /// the time, bucket range and count of each cell. the SVG is wrapped in a
+    /// self-contained HTML page if the file name ends with `.html`
+    LatencySvg(T, String),
+    /// Save the percentiles of each second of the latency heatmaps which store
+    /// the delta between start and stop time for each `Sample`, as a time
+    /// series with one row per second. the file is JSON if its name ends with
+    /// `.json` and CSV otherwise
+    LatencySeries(T, String),
     /// Creates a trace file of the value heatmaps which store counts
     /// asccociated with each `Sample`
     ValueTrace(T, String),
     /// Generate a PNG plot of the value heatmaps which store counts asccociated
-    /// with each `Sample`
-    ValueWaterfall(T, String),
+    /// with each `Sample`, rendered with the given options
+    ValueWaterfall(T, String, WaterfallOptions),
+    /// Keep a counter for every metric, registering a `Count` interest for
+    /// each metric when its first `Sample` is received. at most
+    /// `Config::auto_channels` metrics are registered by wildcard interests
+    AllCount,
+    /// Calculate latency percentiles for every metric, registering a
+    /// `LatencyPercentile` interest for each metric when its first `Sample` is
+    /// received. at most `Config::auto_channels` metrics are registered by
+    /// wildcard interests
+    AllLatencyPercentile,
 }
 
 #[derive(Clone)]
@@ -43,6 +75,27 @@ pub enum ControlMessage<T> {
     AddInterest(Interest<T>),
     RemoveInterest(Interest<T>),
     SnapshotMeters(SyncSender<Meters<T>>),
+    SnapshotHistory(usize, SyncSender<Vec<Meters<T>>>),
+    SnapshotBuckets(SyncSender<Buckets>),
+    RenderWaterfall(String, SyncSender<Result<Vec<u8>, io::Error>>),
+    RenderTrace(String, SyncSender<Result<Vec<u8>, io::Error>>),
+    RenderSvg(String, bool, SyncSender<Result<String, io::Error>>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// a Retention policy decides what is reported for the percentiles of a
+/// metric which had no samples in a window
+///
+/// counts are cumulative and gauges hold the last value set, so they are
+/// always reported. readings for interests which were removed are dropped at
+/// the end of the next window
+pub enum Retention {
+    /// report zero for each percentile
+    Zero,
+    /// report the percentiles from the previous window
+    Last,
+    /// do not report the percentiles
+    Omit,
 }
 
 #[derive(Clone)]
@@ -51,17 +104,17 @@ pub struct Percentile(pub String, pub f64);
 
 // helper function to populate the default `Percentile`s to report
 pub fn default_percentiles() -> Vec<Percentile> {
-    let mut p = Vec::new();
-    p.push(Percentile("min".to_owned(), 0.0));
-    p.push(Percentile("p50".to_owned(), 50.0));
-    p.push(Percentile("p75".to_owned(), 75.0));
-    p.push(Percentile("p90".to_owned(), 90.0));
-    p.push(Percentile("p95".to_owned(), 95.0));
-    p.push(Percentile("p99".to_owned(), 99.0));
-    p.push(Percentile("p999".to_owned(), 99.9));
-    p.push(Percentile("p9999".to_owned(), 99.99));
-    p.push(Percentile("max".to_owned(), 100.0));
-    p
+    vec![
+        Percentile("min".to_owned(), 0.0),
+        Percentile("p50".to_owned(), 50.0),
+        Percentile("p75".to_owned(), 75.0),
+        Percentile("p90".to_owned(), 90.0),
+        Percentile("p95".to_owned(), 95.0),
+        Percentile("p99".to_owned(), 99.0),
+        Percentile("p999".to_owned(), 99.9),
+        Percentile("p9999".to_owned(), 99.99),
+        Percentile("max".to_owned(), 100.0),
+    ]
 }
 
 // helper function to populate the default `Taus`s to report
@@ -74,6 +127,12 @@ pub fn default_taus() -> Vec<usize> {
     t
 }
 
+// helper function to read the wall-clock time in nanoseconds since the epoch
+pub fn unix_time() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.as_secs() * SECOND + now.subsec_nanos() as u64
+}
+
 // helper function for tests, ignore dead_code warnings
 #[allow(dead_code)]
 pub fn is_between(value: f64, min: f64, max: f64) -> bool {
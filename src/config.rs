@@ -7,7 +7,11 @@ use histogram::Histogram;
 use receiver::Receiver;
 use std::fmt::Display;
 use std::hash::Hash;
+use std::io;
+use sink::{Sink, SinkFactory};
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 /// a configuration struct for customizing `Receiver`
@@ -36,14 +40,43 @@ pub struct Config<T> {
     pub service_mode: bool,
     /// set an optional delay between calls to poll
     pub poll_delay: Option<Duration>,
-    /// save a latency heatmap trace to the given file
+    /// save a latency heatmap trace to the given file for each metric with a
+    /// `LatencyPercentile` interest
     pub trace_file: Option<String>,
-    /// save a waterfal png of the latency heatmap to the given file
+    /// save a waterfall png of the latency heatmap to the given file for each
+    /// metric with a `LatencyPercentile` interest
     pub waterfall_file: Option<String>,
     /// the shared `Heatmap` configuration
     pub heatmap_config: heatmap::Config,
     /// the shared `Histogram` configuration
     pub histogram_config: histogram::Config,
+    /// what to report for the percentiles of a metric with no samples in a
+    /// window. Default: `Retention::Zero`
+    pub retention: Retention,
+    /// the horizons, in seconds, over which percentiles are also reported by
+    /// merging the histograms of each window. Default: none
+    pub rollups: Vec<usize>,
+    /// the number of windows of `Meters` kept for `Controller::get_history`,
+    /// this bounds the memory used by the history. Default: 0
+    pub history: usize,
+    /// keep the heatmaps covering the trailing `duration * windows` seconds
+    /// instead of clearing them after each run. Default: false
+    pub rolling_heatmaps: bool,
+    /// the directory trace and waterfall files are saved in. Default: the
+    /// working directory
+    pub artifact_directory: Option<PathBuf>,
+    /// the number of files kept for each trace and waterfall file name
+    /// template, 0 keeps all. Default: 0
+    pub artifact_retention: usize,
+    /// the number of sets of `Labels` recorded for each metric, samples with
+    /// further sets are only recorded for the metric. Default: 100
+    pub label_cardinality: usize,
+    /// the number of metrics registered by `AllCount` and
+    /// `AllLatencyPercentile` interests. Default: 1000
+    pub auto_channels: usize,
+    /// the factories of the `Sink`s called at the end of each window and run,
+    /// each `Receiver` builds its own sinks from them
+    pub sinks: Vec<SinkFactory<T>>,
 }
 
 impl<T: Hash + Eq + Send + Display + Clone> Default for Config<T> {
@@ -64,6 +97,15 @@ impl<T: Hash + Eq + Send + Display + Clone> Default for Config<T> {
             waterfall_file: None,
             heatmap_config: heatmap_config,
             histogram_config: histogram_config,
+            retention: Retention::Zero,
+            history: 0,
+            rollups: Vec::new(),
+            rolling_heatmaps: false,
+            artifact_directory: None,
+            artifact_retention: 0,
+            label_cardinality: 100,
+            auto_channels: 1000,
+            sinks: Vec::new(),
         }
     }
 }
@@ -160,26 +202,37 @@ impl<T: Hash + Eq + Send + Display + Clone> Config<T> {
         self
     }
 
-    /// set the heatmap trace file
+    /// set the default heatmap trace file
+    ///
+    /// a trace is saved after each run for every metric with a
+    /// `LatencyPercentile` interest and no `LatencyTrace` interest. the
+    /// `{metric}` placeholder is replaced with the name of the metric, if
+    /// there is none the name is added before the extension
     ///
     /// # Example
     /// ```
     /// # use tic::Receiver;
     /// let mut c = Receiver::<usize>::configure();
-    /// c.trace_file("/tmp/heatmap.trace".to_owned()); // heatmap trace will write here
+    /// c.trace_file("/tmp/{metric}.trace".to_owned()); // heatmap traces will write here
     /// ```
     pub fn trace_file(mut self, path: String) -> Self {
         self.trace_file = Some(path);
         self
     }
 
-    /// set the heatmap trace file
+    /// set the default waterfall file
+    ///
+    /// a waterfall is rendered after each run for every metric with a
+    /// `LatencyPercentile` interest and no `LatencyWaterfall` interest. the
+    /// `{metric}` placeholder is replaced with the name of the metric, if
+    /// there is none the name is added before the extension. it must differ
+    /// from the trace file
     ///
     /// # Example
     /// ```
     /// # use tic::Receiver;
     /// let mut c = Receiver::<usize>::configure();
-    /// c.waterfall_file("/tmp/waterfall.png".to_owned()); // waterfall png will render here
+    /// c.waterfall_file("/tmp/{metric}.png".to_owned()); // waterfall pngs will render here
     /// ```
     pub fn waterfall_file(mut self, path: String) -> Self {
         self.waterfall_file = Some(path);
@@ -211,8 +264,177 @@ impl<T: Hash + Eq + Send + Display + Clone> Config<T> {
         self
     }
 
+    /// set the directory trace and waterfall files are saved in: default the
+    /// working directory
+    ///
+    /// the file names of trace and waterfall interests are templates, in
+    /// which `{date}` is replaced with the UTC time of the save, `{window}`
+    /// with the number of windows which have closed and `{run}` with the
+    /// number of runs which completed before this one
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::{Interest, Receiver, WaterfallOptions};
+    /// let mut receiver = Receiver::<usize>::configure()
+    ///     .artifact_directory("/tmp/tic")
+    ///     .artifact_retention(24)
+    ///     .build();
+    /// receiver.add_interest(Interest::LatencyWaterfall(
+    ///     1,
+    ///     "ok_{date}_{window}.png".to_owned(),
+    ///     WaterfallOptions::default(),
+    /// ));
+    /// ```
+    pub fn artifact_directory<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.artifact_directory = Some(directory.as_ref().to_path_buf());
+        self
+    }
+
+    /// set the number of files to keep for each trace and waterfall file name
+    /// template, once exceeded the oldest files are removed: default 0, which
+    /// keeps all files
+    ///
+    /// only the files saved by this `Receiver` are removed
+    pub fn artifact_retention(mut self, count: usize) -> Self {
+        self.artifact_retention = count;
+        self
+    }
+
+    /// enable rolling heatmaps: default false
+    ///
+    /// in service mode the heatmaps are normally cleared after each run of
+    /// `windows` windows. rolling heatmaps instead evict their oldest slice
+    /// as each new one starts, so waterfalls and traces always cover the
+    /// trailing `duration * windows` seconds and can be rendered at any time
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Receiver;
+    /// let mut c = Receiver::<usize>::configure();
+    /// c.service(true).rolling_heatmaps(true);
+    /// ```
+    pub fn rolling_heatmaps(mut self, enabled: bool) -> Self {
+        self.rolling_heatmaps = enabled;
+        self
+    }
+
+    /// set the `Retention` policy for percentiles of metrics with no samples
+    /// in a window: default `Retention::Zero`
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::{Receiver, Retention};
+    /// let mut c = Receiver::<usize>::configure();
+    /// c.retention(Retention::Last); // repeat the previous percentiles
+    /// ```
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// add a horizon, in seconds, over which percentiles are reported in
+    /// addition to those of each window
+    ///
+    /// the percentiles are calculated by merging the histograms of the
+    /// windows within the horizon, and are named with the horizon as a
+    /// suffix, eg: `ok_p99_nanoseconds_5m`. the horizon is rounded down to a
+    /// whole number of windows
+    ///
+    /// the non-empty buckets of each window are kept for the longest horizon,
+    /// plus one histogram per horizon, for each percentile interest
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Receiver;
+    /// let mut c = Receiver::<usize>::configure();
+    /// c.rollup(60).rollup(300).rollup(3600);
+    /// ```
+    pub fn rollup(mut self, seconds: usize) -> Self {
+        self.rollups.push(seconds);
+        self
+    }
+
+    /// set the number of windows of `Meters` to keep in the history: default 0
+    ///
+    /// the history holds a copy of the `Meters` from each of the most recent
+    /// windows, so it uses roughly `windows` times the memory of a snapshot
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Receiver;
+    /// let mut c = Receiver::<usize>::configure();
+    /// c.history(600); // keep the last 10 minutes of 1 second windows
+    /// ```
+    pub fn history(mut self, windows: usize) -> Self {
+        self.history = windows;
+        self
+    }
+
+    /// set the number of sets of `Labels` recorded for each metric: default 100
+    ///
+    /// the limit is shared by the counts and latencies of the metric, a set of
+    /// labels holds one slot for as long as either of them remains. samples
+    /// with new labels beyond the limit are only recorded for the metric, and
+    /// are counted by `Window::labels_dropped`
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Receiver;
+    /// let mut c = Receiver::<usize>::configure();
+    /// c.label_cardinality(20); // eg: 20 combinations of endpoint and status
+    /// ```
+    pub fn label_cardinality(mut self, limit: usize) -> Self {
+        self.label_cardinality = limit;
+        self
+    }
+
+    /// set the number of metrics registered by `AllCount` and
+    /// `AllLatencyPercentile` interests: default 1000
+    ///
+    /// samples for further metrics are not recorded unless the metric has an
+    /// interest of its own, and are counted by `Window::auto_rejected`
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Receiver;
+    /// let mut c = Receiver::<usize>::configure();
+    /// c.auto_channels(64); // eg: one metric per shard
+    /// ```
+    pub fn auto_channels(mut self, limit: usize) -> Self {
+        self.auto_channels = limit;
+        self
+    }
+
+    /// register a `Sink` to be called at the end of each window and run. the
+    /// factory is called once for each `Receiver` built from the `Config` or
+    /// its clones, so each receiver has a sink of its own
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::{Receiver, Statsd};
+    /// let mut c = Receiver::<usize>::configure();
+    /// c.sink(|| Box::new(Statsd::new("127.0.0.1:8125").prefix("tic")));
+    /// ```
+    pub fn sink<F>(mut self, factory: F) -> Self
+    where
+        F: FnMut() -> Box<dyn Sink<T>> + Send + 'static,
+    {
+        self.sinks.push(Arc::new(Mutex::new(Box::new(factory))));
+        self
+    }
+
     /// Build a new Receiver based on the current configuration
+    ///
+    /// if the trace file and the waterfall file are the same path only the
+    /// trace is saved, use `try_build` to reject such a configuration
     pub fn build(self) -> Receiver<T> {
         Receiver::configured(self)
     }
+
+    /// Build a new Receiver based on the current configuration, or an
+    /// `InvalidInput` error if the trace file and the waterfall file are the
+    /// same path
+    pub fn try_build(self) -> Result<Receiver<T>, io::Error> {
+        Receiver::try_configured(self)
+    }
 }
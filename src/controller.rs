@@ -1,3 +1,4 @@
+use buckets::Buckets;
 use common::ControlMessage;
 use data::Meters;
 use mio_extras::channel;
@@ -11,6 +12,7 @@ pub struct Controller<T> {
 }
 
 impl<T> Controller<T> {
+    /// create a `Controller` which sends `ControlMessage`s on the given channel
     pub fn new(control_tx: channel::SyncSender<ControlMessage<T>>) -> Controller<T> {
         Controller { control_tx: control_tx }
     }
@@ -20,18 +22,102 @@ impl<T> Controller<T> {
     /// this will block until the `Receiver` responds
     pub fn get_meters(&self) -> Result<Meters<T>, io::Error> {
         let (tx, rx) = mpsc::sync_channel(1);
-        let msg = ControlMessage::SnapshotMeters(tx);
+        self.send(ControlMessage::SnapshotMeters(tx))?;
+        match rx.recv() {
+            Ok(result) => Ok(result),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to receive snapshot",
+            )),
+        }
+    }
+
+    /// takes a snapshot of the `Meters` from up to `windows` of the most
+    /// recent windows, oldest first. the `Receiver` only keeps as many windows
+    /// as set by `Config::history`
+    ///
+    /// this will block until the `Receiver` responds
+    pub fn get_history(&self, windows: usize) -> Result<Vec<Meters<T>>, io::Error> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(ControlMessage::SnapshotHistory(windows, tx))?;
+        match rx.recv() {
+            Ok(result) => Ok(result),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to receive history",
+            )),
+        }
+    }
+
+    /// takes a snapshot of the raw buckets of the lifetime latency histogram
+    /// of each metric with a `LatencyPercentileCumulative` interest
+    ///
+    /// this will block until the `Receiver` responds
+    pub fn get_buckets(&self) -> Result<Buckets, io::Error> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(ControlMessage::SnapshotBuckets(tx))?;
+        match rx.recv() {
+            Ok(result) => Ok(result),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to receive buckets",
+            )),
+        }
+    }
+
+    /// renders a PNG waterfall of the current latency heatmap for the named
+    /// metric, the metric is matched against the `Display` of the channel
+    ///
+    /// this will block until the `Receiver` responds
+    pub fn get_waterfall(&self, metric: &str) -> Result<Vec<u8>, io::Error> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(ControlMessage::RenderWaterfall(metric.to_owned(), tx))?;
+        match rx.recv() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to receive waterfall",
+            )),
+        }
+    }
+
+    /// renders a trace of the current latency heatmap for the named metric,
+    /// the metric is matched against the `Display` of the channel
+    ///
+    /// this will block until the `Receiver` responds
+    pub fn get_trace(&self, metric: &str) -> Result<Vec<u8>, io::Error> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(ControlMessage::RenderTrace(metric.to_owned(), tx))?;
+        match rx.recv() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to receive trace",
+            )),
+        }
+    }
+
+    /// renders an SVG of the current latency heatmap for the named metric,
+    /// with a tooltip for each cell. if `html` is set the SVG is wrapped in a
+    /// self-contained HTML page
+    ///
+    /// this will block until the `Receiver` responds
+    pub fn get_svg(&self, metric: &str, html: bool) -> Result<String, io::Error> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(ControlMessage::RenderSvg(metric.to_owned(), html, tx))?;
+        match rx.recv() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to receive svg",
+            )),
+        }
+    }
 
+    // send a control message to the `Receiver`
+    fn send(&self, msg: ControlMessage<T>) -> Result<(), io::Error> {
         match self.control_tx.try_send(msg) {
-            Ok(_) => {
-                match rx.recv() {
-                    Ok(result) => Ok(result),
-                    Err(_) => Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "failed to receive snapshot",
-                    )),
-                }
-            }
+            Ok(_) => Ok(()),
             Err(e) => {
                 match e {
                     TrySendError::Io(e) => {
@@ -41,7 +127,7 @@ impl<T> Controller<T> {
                     TrySendError::Full(_) |
                     TrySendError::Disconnected(_) => Err(io::Error::new(
                         io::ErrorKind::Other,
-                        "failed to send snapshot command",
+                        "failed to send control message",
                     )),
                 }
             }
@@ -1,6 +1,7 @@
 // `Counters` is a map of u64 counters, keyed by metric
 
 use fnv::FnvHashMap;
+use std::collections::hash_map;
 use std::hash::Hash;
 
 pub struct Counters<T> {
@@ -20,6 +21,19 @@ impl<T: Hash + Eq> Counters<T> {
         self.data.remove(&key);
     }
 
+    pub fn contains(&self, key: &T) -> bool {
+        self.data.contains_key(key)
+    }
+
+    /// keep only the counters whose keys match the predicate
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut keep: F) {
+        self.data.retain(|key, _| keep(key));
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, T, u64> {
+        self.data.iter()
+    }
+
     #[allow(dead_code)]
     pub fn increment(&mut self, key: T) {
         self.increment_by(key, 1);
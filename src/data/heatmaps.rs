@@ -4,32 +4,98 @@ extern crate heatmap;
 
 use fnv::FnvHashMap;
 use heatmap::Heatmap;
+use histogram::{self, Histogram};
+use common::Percentile;
+use render::{self, Scale, Unit, WaterfallOptions};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::File;
 use std::hash::Hash;
-use waterfall::Waterfall;
+use std::io::{self, BufWriter, Write};
+use trace;
+
+const SLICE_DURATION: u64 = 1_000_000_000;
+const MAX_VALUE: u64 = 60 * SLICE_DURATION;
+
+// the samples of one slice of a rolling heatmap
+struct Slice {
+    start: u64,
+    histogram: Histogram,
+}
 
 pub struct Heatmaps<T> {
     config: heatmap::Config,
+    histogram_config: histogram::Config,
+    slices: usize,
+    t0: u64,
+    rolling: bool,
+    unit: Unit,
+    recent: FnvHashMap<T, VecDeque<Slice>>,
     pub data: FnvHashMap<T, Heatmap>,
 }
 
 impl<T: Hash + Eq> Heatmaps<T> {
     pub fn new(slices: usize, t0: u64) -> Heatmaps<T> {
         let config = Heatmap::configure()
-            .slice_duration(1_000_000_000)
+            .slice_duration(SLICE_DURATION)
             .num_slices(slices)
             .precision(2)
             .start(t0);
         Heatmaps {
             config: config,
+            histogram_config: Histogram::configure().max_value(MAX_VALUE).precision(2),
+            slices: slices,
+            t0: t0,
+            rolling: false,
+            unit: Unit::Count,
+            recent: FnvHashMap::default(),
             data: FnvHashMap::default(),
         }
     }
 
-    pub fn init(&mut self, key: T) {
+    /// in rolling mode the samples are kept per slice, and the oldest slices
+    /// are evicted so the heatmaps always cover the trailing `slices` seconds
+    /// as of the last call to `roll`
+    pub fn rolling(mut self, enabled: bool) -> Self {
+        self.rolling = enabled;
+        self
+    }
+
+    /// build the heatmaps from the given config, keeping the slice duration,
+    /// number of slices and start time
+    pub fn heatmap_config(mut self, config: heatmap::Config) -> Self {
+        self.config = config
+            .slice_duration(SLICE_DURATION)
+            .num_slices(self.slices)
+            .start(self.t0);
+        self
+    }
+
+    /// build the histogram of each slice of a rolling heatmap from the given
+    /// config
+    pub fn histogram_config(mut self, config: histogram::Config) -> Self {
+        self.histogram_config = config;
+        self
+    }
+
+    /// the unit of the values, which is used to label waterfalls
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    pub fn init(&mut self, key: T)
+    where
+        T: Clone,
+    {
+        if self.rolling {
+            self.recent.insert(key.clone(), VecDeque::new());
+        }
         self.data.insert(key, self.config.build().unwrap());
     }
 
     pub fn remove(&mut self, key: T) {
+        self.recent.remove(&key);
         self.data.remove(&key);
     }
 
@@ -38,31 +104,402 @@ impl<T: Hash + Eq> Heatmaps<T> {
     }
 
     pub fn increment_by(&mut self, key: T, start: u64, value: u64, count: u64) {
+        if self.rolling {
+            if let Some(slices) = self.recent.get_mut(&key) {
+                if start < self.t0 {
+                    return;
+                }
+                let slice = self.t0 + (start - self.t0) / SLICE_DURATION * SLICE_DURATION;
+                // samples usually arrive in order, so search from the newest
+                let position = slices.iter().rposition(|s| s.start <= slice);
+                let index = match position {
+                    Some(i) if slices[i].start == slice => i,
+                    _ => {
+                        let i = position.map(|i| i + 1).unwrap_or(0);
+                        slices.insert(
+                            i,
+                            Slice {
+                                start: slice,
+                                histogram: self.histogram_config.build().unwrap(),
+                            },
+                        );
+                        i
+                    }
+                };
+                let _ = slices[index].histogram.increment_by(value, count);
+            }
+            return;
+        }
         if let Some(h) = self.data.get_mut(&key) {
             let _ = h.increment_by(start, value, count);
-            return;
         }
     }
 
-    pub fn trace(&mut self, key: T, file: String) {
-        if let Some(h) = self.data.get_mut(&key) {
-            h.save(file);
+    pub fn trace(&self, key: T, file: String) -> Result<(), io::Error> {
+        match self.data.get(&key) {
+            Some(h) => trace::write(h, &mut BufWriter::new(File::create(file)?)),
+            None => Err(not_found()),
         }
     }
 
-    pub fn waterfall(&self, key: T, file: String) {
-        if let Some(h) = self.data.get(&key) {
-            trace!("trace for heatmap with: {} slices", h.num_slices());
-            let mut waterfall = Waterfall::new();
-            waterfall.render_png(h, file);
+    pub fn waterfall(
+        &self,
+        key: T,
+        file: String,
+        options: &WaterfallOptions,
+    ) -> Result<(), io::Error> {
+        let png = self.render_waterfall(key, options)?;
+        File::create(file)?.write_all(&png)
+    }
+
+    /// save the given percentiles of each slice for the given key, as JSON if
+    /// the file name ends with `.json` and as CSV otherwise. slices after the
+    /// last with samples are left out
+    pub fn series(
+        &self,
+        key: T,
+        file: String,
+        percentiles: &[Percentile],
+    ) -> Result<(), io::Error> {
+        let h = match self.data.get(&key) {
+            Some(h) => h,
+            None => return Err(not_found()),
+        };
+        let values: Vec<f64> = percentiles.iter().map(|p| p.1).collect();
+        let mut series = trace::slice_percentiles(h, &values);
+        while series.last().map(|s| s.1.iter().all(|v| v.is_none())).unwrap_or(false) {
+            series.pop();
         }
+        let output = if file.ends_with(".json") {
+            series_json(&series, percentiles)
+        } else {
+            series_csv(&series, percentiles)
+        };
+        File::create(file)?.write_all(output.as_bytes())
     }
 
     pub fn clear(&mut self) {
+        for slices in self.recent.values_mut() {
+            slices.clear();
+        }
         for heatmap in self.data.values_mut() {
             heatmap.clear();
         }
     }
+
+    // the start of the oldest slice of a rolling heatmap at the given time,
+    // aligned to the start time of the `Heatmaps`
+    fn rolling_start(&self, now: u64) -> u64 {
+        if now < self.t0 {
+            return self.t0;
+        }
+        // the current slice is one of the trailing slices, unless there are
+        // none to keep
+        let current = (now - self.t0) / SLICE_DURATION;
+        let oldest = (current + 1).saturating_sub(self.slices as u64);
+        self.t0 + oldest * SLICE_DURATION
+    }
+
+    /// evict the slices which are older than the trailing `slices` seconds
+    /// and rebuild the heatmaps from the remaining slices. this does nothing
+    /// unless in rolling mode
+    pub fn roll(&mut self, now: u64) {
+        if !self.rolling {
+            return;
+        }
+        let start = self.rolling_start(now);
+        for (key, slices) in &mut self.recent {
+            while slices.front().map(|s| s.start < start).unwrap_or(false) {
+                slices.pop_front();
+            }
+            let mut heatmap = self.config.start(start).build().unwrap();
+            for slice in slices.iter() {
+                for bucket in &slice.histogram {
+                    if bucket.count() > 0 {
+                        let _ = heatmap.increment_by(slice.start, bucket.value(), bucket.count());
+                    }
+                }
+            }
+            if let Some(h) = self.data.get_mut(key) {
+                *h = heatmap;
+            }
+        }
+    }
+
+    // render a waterfall PNG for the given key
+    pub fn render_waterfall(
+        &self,
+        key: T,
+        options: &WaterfallOptions,
+    ) -> Result<Vec<u8>, io::Error> {
+        match self.data.get(&key) {
+            Some(h) => {
+                trace!("waterfall for heatmap with: {} slices", h.num_slices());
+                render::render(h, options, self.unit).png()
+            }
+            None => Err(not_found()),
+        }
+    }
+
+    // render a trace file for the given key and return its contents
+    pub fn render_trace(&self, key: T) -> Result<Vec<u8>, io::Error> {
+        match self.data.get(&key) {
+            Some(h) => {
+                let mut buffer = Vec::new();
+                trace::write(h, &mut buffer)?;
+                Ok(buffer)
+            }
+            None => Err(not_found()),
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone + Display> Heatmaps<T> {
+    // find the key whose `Display` matches the given name
+    pub fn find(&self, name: &str) -> Option<T> {
+        self.data.keys().find(|k| k.to_string() == name).cloned()
+    }
+
+    /// save an SVG of the heatmap for the given key, which is wrapped in an
+    /// HTML page if the file name ends with `.html`
+    pub fn svg(&self, key: T, file: String) -> Result<(), io::Error> {
+        let html = file.ends_with(".html");
+        let svg = self.render_svg(key, html)?;
+        File::create(file)?.write_all(svg.as_bytes())
+    }
+
+    // render an SVG of the heatmap for the given key, optionally as an HTML
+    // page titled with the key
+    pub fn render_svg(&self, key: T, html: bool) -> Result<String, io::Error> {
+        match self.data.get(&key) {
+            Some(h) => {
+                // with tooltips for the counts, a log scale keeps rare
+                // outliers visible
+                let options = WaterfallOptions::default().scale(Scale::Log);
+                let svg = render::svg(h, &options, self.unit);
+                if html {
+                    Ok(render::html(&svg, &key.to_string()))
+                } else {
+                    Ok(svg)
+                }
+            }
+            None => Err(not_found()),
+        }
+    }
+}
+
+// a header of the timestamp and percentile labels, then one row per slice
+// with empty fields for slices without samples
+fn series_csv(series: &[(u64, Vec<Option<u64>>)], percentiles: &[Percentile]) -> String {
+    let mut output = "timestamp".to_owned();
+    for percentile in percentiles {
+        output.push_str(&format!(",{}", percentile.0));
+    }
+    output.push('\n');
+    for &(start, ref values) in series {
+        output.push_str(&start.to_string());
+        for value in values {
+            output.push(',');
+            if let Some(value) = *value {
+                output.push_str(&value.to_string());
+            }
+        }
+        output.push('\n');
+    }
+    output
+}
+
+// an array of objects keyed by timestamp and percentile label, with null for
+// slices without samples
+fn series_json(series: &[(u64, Vec<Option<u64>>)], percentiles: &[Percentile]) -> String {
+    let rows: Vec<String> = series
+        .iter()
+        .map(|&(start, ref values)| {
+            let mut row = format!("{{\"timestamp\":{}", start);
+            for (percentile, value) in percentiles.iter().zip(values) {
+                match *value {
+                    Some(value) => row.push_str(&format!(",\"{}\":{}", percentile.0, value)),
+                    None => row.push_str(&format!(",\"{}\":null", percentile.0)),
+                }
+            }
+            row.push('}');
+            row
+        })
+        .collect();
+    format!("[{}]\n", rows.join(","))
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no heatmap for metric")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // used to give each temporary file a unique name
+    static FILE_ID: AtomicUsize = AtomicUsize::new(0);
+
+    // a unique path in the temporary directory
+    fn temp_path(extension: &str) -> PathBuf {
+        let id = FILE_ID.fetch_add(1, Ordering::Relaxed);
+        env::temp_dir().join(format!("tic_{}_{}.{}", process::id(), id, extension))
+    }
+
+    // read back a saved artifact and remove the file
+    fn read_and_remove(path: PathBuf) -> Result<Vec<u8>, io::Error> {
+        let mut buffer = Vec::new();
+        let result = File::open(&path).and_then(|mut f| f.read_to_end(&mut buffer));
+        let _ = fs::remove_file(&path);
+        result.map(|_| buffer)
+    }
+
+    #[test]
+    fn trace() {
+        let mut heatmaps = Heatmaps::<usize>::new(2, 0);
+        heatmaps.init(1);
+        heatmaps.increment(1, 0, 100);
+        let rendered = String::from_utf8(heatmaps.render_trace(1).unwrap()).unwrap();
+        assert_eq!(rendered, "2 0 1000000000 1000000000 2 0\n0 100 1\n");
+
+        let file = temp_path("trace");
+        heatmaps.trace(1, file.to_string_lossy().into_owned()).unwrap();
+        assert_eq!(read_and_remove(file).unwrap(), rendered.into_bytes());
+
+        // an unwritable path is an error rather than a panic
+        let directory = temp_path("d");
+        fs::create_dir(&directory).unwrap();
+        let result = heatmaps.trace(1, directory.to_string_lossy().into_owned());
+        fs::remove_dir(&directory).unwrap();
+        assert!(result.is_err());
+        assert_eq!(heatmaps.render_trace(2).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn rolling() {
+        let t0 = 500_000_000;
+        let mut heatmaps = Heatmaps::<usize>::new(3, t0).rolling(true);
+        heatmaps.init(1);
+
+        for second in 0..5 {
+            heatmaps.increment(1, t0 + second * SLICE_DURATION + 1, 100);
+        }
+        // out of order samples land in their own slice
+        heatmaps.increment(1, t0 + SLICE_DURATION + 2, 200);
+        {
+            let starts: Vec<u64> = heatmaps.recent[&1].iter().map(|s| s.start).collect();
+            let expected: Vec<u64> = (0..5).map(|s| t0 + s * SLICE_DURATION).collect();
+            assert_eq!(starts, expected);
+            assert_eq!(heatmaps.recent[&1][1].histogram.entries(), 2);
+        }
+
+        // slices stay aligned to t0 when the oldest are evicted
+        heatmaps.roll(t0 + 4 * SLICE_DURATION + SLICE_DURATION / 2);
+        let starts: Vec<u64> = heatmaps.recent[&1].iter().map(|s| s.start).collect();
+        let expected: Vec<u64> = (2..5).map(|s| t0 + s * SLICE_DURATION).collect();
+        assert_eq!(starts, expected);
+
+        heatmaps.roll(t0 + 10 * SLICE_DURATION);
+        assert!(heatmaps.recent[&1].is_empty());
+    }
+
+    #[test]
+    fn rolling_without_slices() {
+        let t0 = 500_000_000;
+        let mut heatmaps = Heatmaps::<usize>::new(0, t0).rolling(true);
+        heatmaps.init(1);
+        heatmaps.increment(1, t0 + 1, 100);
+        heatmaps.roll(t0 + SLICE_DURATION / 2);
+        assert!(heatmaps.recent[&1].is_empty());
+        assert_eq!(values(&heatmaps.data[&1]), Vec::<u64>::new());
+    }
+
+    // the values of the non-empty buckets of each slice of the heatmap
+    fn values(heatmap: &Heatmap) -> Vec<u64> {
+        let mut values = Vec::new();
+        for slice in heatmap {
+            let histogram = slice.histogram();
+            for bucket in &histogram {
+                if bucket.count() > 0 {
+                    values.push(bucket.value());
+                }
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn configured() {
+        let heatmap_config = Heatmap::configure().max_value(10_000).precision(3);
+        let histogram_config = Histogram::configure().max_value(1_000).precision(3);
+        let mut heatmaps = Heatmaps::<usize>::new(3, 0)
+            .heatmap_config(heatmap_config)
+            .histogram_config(histogram_config)
+            .rolling(true);
+        heatmaps.init(1);
+
+        // values above the max of the histogram config are not kept by the
+        // slices, and the rebuilt heatmap has the precision of its config
+        heatmaps.increment(1, 0, 500);
+        heatmaps.increment(1, 0, 5_000);
+        heatmaps.roll(SLICE_DURATION);
+        assert_eq!(values(&heatmaps.data[&1]), vec![500]);
+
+        let mut heatmaps = Heatmaps::<usize>::new(3, 0).heatmap_config(heatmap_config);
+        heatmaps.init(1);
+        heatmaps.increment(1, 0, 500);
+        heatmaps.increment(1, 0, 50_000);
+        assert_eq!(heatmaps.data[&1].num_slices(), 3);
+        assert_eq!(values(&heatmaps.data[&1]), vec![500]);
+    }
+
+    #[test]
+    fn series() {
+        let mut heatmaps = Heatmaps::<usize>::new(10, 0);
+        heatmaps.init(1);
+        for second in 0..3 {
+            heatmaps.increment(1, second * SLICE_DURATION, 1);
+        }
+        heatmaps.increment(1, 2 * SLICE_DURATION, 5);
+        let percentiles = vec![
+            Percentile("p50".to_owned(), 50.0),
+            Percentile("max".to_owned(), 100.0),
+        ];
+
+        let file = temp_path("csv");
+        heatmaps
+            .series(1, file.to_string_lossy().into_owned(), &percentiles)
+            .unwrap();
+        let csv = String::from_utf8(read_and_remove(file).unwrap()).unwrap();
+        // the empty slices after the last sample are left out
+        assert_eq!(
+            csv,
+            "timestamp,p50,max\n0,1,1\n1000000000,1,1\n2000000000,5,5\n"
+        );
+
+        let series = vec![(0, vec![Some(1), None])];
+        assert_eq!(
+            series_json(&series, &percentiles),
+            "[{\"timestamp\":0,\"p50\":1,\"max\":null}]\n"
+        );
+    }
+
+    #[test]
+    fn rolling_start() {
+        let heatmaps = Heatmaps::<usize>::new(60, 1000);
+        assert_eq!(heatmaps.rolling_start(0), 1000);
+        assert_eq!(heatmaps.rolling_start(1000 + 30 * SLICE_DURATION), 1000);
+        assert_eq!(
+            heatmaps.rolling_start(1000 + 90 * SLICE_DURATION + 5),
+            1000 + 31 * SLICE_DURATION
+        );
+    }
 }
 
 #[cfg(feature = "benchmark")]
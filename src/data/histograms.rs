@@ -28,6 +28,12 @@ impl<T: Hash + Eq> Histograms<T> {
         Default::default()
     }
 
+    /// build the histogram of each key from the given config
+    pub fn histogram_config(mut self, config: histogram::Config) -> Self {
+        self.config = config;
+        self
+    }
+
     pub fn increment(&mut self, key: T, duration: u64) {
         self.increment_by(key, duration, 1);
     }
@@ -35,7 +41,6 @@ impl<T: Hash + Eq> Histograms<T> {
     pub fn increment_by(&mut self, key: T, duration: u64, count: u64) {
         if let Some(h) = self.data.get_mut(&key) {
             let _ = h.increment_by(duration, count);
-            return;
         }
     }
 
@@ -47,12 +52,28 @@ impl<T: Hash + Eq> Histograms<T> {
         self.data.remove(&key);
     }
 
+    pub fn contains(&self, key: &T) -> bool {
+        self.data.contains_key(key)
+    }
+
+    /// keep only the histograms whose keys match the predicate
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut keep: F) {
+        self.data.retain(|key, _| keep(key));
+    }
+
     pub fn clear(&mut self) {
         for histogram in self.data.values_mut() {
             histogram.clear();
         }
     }
 
+    pub fn entries(&self, key: T) -> u64 {
+        if let Some(h) = self.data.get(&key) {
+            return h.entries();
+        }
+        0
+    }
+
     pub fn percentile(&self, key: T, percentile: f64) -> Result<u64, &'static str> {
         if let Some(h) = self.data.get(&key) {
             return h.percentile(percentile);
@@ -145,7 +166,7 @@ mod test {
             let v = between.ind_sample(&mut rng);
             h.increment(key.clone(), v);
         }
-        for t in vec![25.0, 50.0, 75.0, 90.0, 99.0, 99.9, 99.99] {
+        for t in [25.0, 50.0, 75.0, 90.0, 99.0, 99.9, 99.99] {
             let v = h.percentile(key.clone(), t).unwrap_or_else(|_| {
                 println!("error percentile: {}", t);
                 panic!("error")
@@ -0,0 +1,112 @@
+// `Labels` are the key/value dimensions of a `Sample`
+
+use std::fmt;
+
+/// `Labels` are key/value pairs which split the counts and latencies of a
+/// channel into one series per distinct set of labels, eg: by status code
+///
+/// the labels are kept in order of their keys, so the same labels given in any
+/// order are equal
+///
+/// # Example
+/// ```
+/// # use tic::Labels;
+/// let labels = Labels::new().with("status", "500").with("endpoint", "/login");
+/// assert_eq!(labels.get("status"), Some("500"));
+/// assert_eq!(labels.to_string(), "endpoint=\"/login\",status=\"500\"");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Labels {
+    pairs: Vec<(String, String)>,
+}
+
+impl Labels {
+    /// create an empty set of `Labels`
+    pub fn new() -> Labels {
+        Default::default()
+    }
+
+    /// add a label, replacing the value of any label with the same key
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// add a label, replacing the value of any label with the same key
+    pub fn insert(&mut self, key: &str, value: &str) {
+        match self.pairs.binary_search_by(|p| p.0.as_str().cmp(key)) {
+            Ok(i) => self.pairs[i].1 = value.to_owned(),
+            Err(i) => self.pairs.insert(i, (key.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// the value of the label with the given key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match self.pairs.binary_search_by(|p| p.0.as_str().cmp(key)) {
+            Ok(i) => Some(&self.pairs[i].1),
+            Err(_) => None,
+        }
+    }
+
+    /// the key and value of each label, in order of their keys
+    pub fn iter(&self) -> ::std::slice::Iter<'_, (String, String)> {
+        self.pairs.iter()
+    }
+
+    /// the number of labels
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// true if there are no labels
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl fmt::Display for Labels {
+    /// formats the labels as in the Prometheus exposition format, without the
+    /// surrounding braces, eg: `status="500"`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.pairs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}=\"{}\"", key, escape(value))?;
+        }
+        Ok(())
+    }
+}
+
+// escape a label value for the Prometheus exposition format, which matches
+// the escaping of JSON strings for these characters
+pub fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordering() {
+        let a = Labels::new().with("status", "500").with("endpoint", "/");
+        let b = Labels::new().with("endpoint", "/").with("status", "200").with("status", "500");
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 2);
+        assert_eq!(a.get("endpoint"), Some("/"));
+        assert_eq!(a.get("method"), None);
+        let keys: Vec<&str> = a.iter().map(|p| p.0.as_str()).collect();
+        assert_eq!(keys, vec!["endpoint", "status"]);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Labels::new().to_string(), "");
+        let labels = Labels::new().with("path", "a\"b\\c\n");
+        assert_eq!(labels.to_string(), "path=\"a\\\"b\\\\c\\n\"");
+    }
+}
@@ -1,28 +1,204 @@
 // `Meters` hold calculated values
 
 use common::Percentile;
+use data::Labels;
 use fnv::FnvHashMap;
+use sink::Window;
+use std::collections::{btree_map, hash_map, BTreeMap};
+use std::fmt;
 use std::fmt::Display;
 use std::hash::Hash;
-use std::marker::PhantomData;
+
+/// a `MeterKind` identifies which statistic of a metric a meter holds
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MeterKind {
+    /// the count of events
+    Count,
+    /// the point-in-time value
+    Value,
+    /// a percentile of sample latencies, identified by its label
+    LatencyPercentile(String),
+    /// a percentile of sample counts, identified by its label
+    ValuePercentile(String),
+    /// the Allan Deviation at the given Tau
+    Adev(usize),
+    /// a percentile of sample latencies over the lifetime of the `Receiver`,
+    /// identified by its label
+    LatencyCumulative(String),
+    /// a percentile of sample latencies over a longer horizon, identified by
+    /// the labels of the percentile and the horizon
+    LatencyRollup(String, String),
+    /// a percentile of sample counts over a longer horizon, identified by the
+    /// labels of the percentile and the horizon
+    ValueRollup(String, String),
+}
+
+impl fmt::Display for MeterKind {
+    /// formats the suffix used in the string representation of the meter
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MeterKind::Count => write!(f, "count"),
+            MeterKind::Value => write!(f, "value"),
+            MeterKind::LatencyPercentile(ref label) => write!(f, "{}_nanoseconds", label),
+            MeterKind::ValuePercentile(ref label) => write!(f, "{}_units", label),
+            MeterKind::Adev(tau) => write!(f, "tau_{}_adev", tau),
+            MeterKind::LatencyCumulative(ref label) => {
+                write!(f, "{}_nanoseconds_cumulative", label)
+            }
+            MeterKind::LatencyRollup(ref label, ref horizon) => {
+                write!(f, "{}_nanoseconds_{}", label, horizon)
+            }
+            MeterKind::ValueRollup(ref label, ref horizon) => {
+                write!(f, "{}_units_{}", label, horizon)
+            }
+        }
+    }
+}
+
+/// a `Reading` is the value held by a meter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reading {
+    /// an integer reading, used by all meters except Allan Deviation
+    Integer(u64),
+    /// a floating point reading, used by Allan Deviation
+    Float(f64),
+}
+
+impl Reading {
+    /// the reading as a floating point value
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Reading::Integer(v) => v as f64,
+            Reading::Float(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reading::Integer(v) => write!(f, "{}", v),
+            Reading::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// a `Meter` is a single structured entry of the `Meters`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Meter<'a, T: 'a> {
+    /// the metric the meter belongs to
+    pub channel: &'a T,
+    /// the statistic held by the meter
+    pub kind: &'a MeterKind,
+    /// the value of the meter
+    pub reading: Reading,
+}
+
+impl<'a, T: Display> Meter<'a, T> {
+    /// the string representation of the meter, eg: `ok_p50_nanoseconds`
+    pub fn name(&self) -> String {
+        format!("{}_{}", self.channel, self.kind)
+    }
+}
+
+/// an iterator over the `Meter`s in `Meters`
+pub struct Iter<'a, T: 'a> {
+    channels: hash_map::Iter<'a, T, FnvHashMap<MeterKind, Reading>>,
+    current: Option<(&'a T, hash_map::Iter<'a, MeterKind, Reading>)>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = Meter<'a, T>;
+
+    fn next(&mut self) -> Option<Meter<'a, T>> {
+        loop {
+            if let Some((channel, ref mut kinds)) = self.current {
+                if let Some((kind, reading)) = kinds.next() {
+                    return Some(Meter {
+                        channel: channel,
+                        kind: kind,
+                        reading: *reading,
+                    });
+                }
+            }
+            match self.channels.next() {
+                Some((channel, kinds)) => self.current = Some((channel, kinds.iter())),
+                None => return None,
+            }
+        }
+    }
+}
+
+/// a `LabelledMeter` is a meter for one set of `Labels` of a metric
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelledMeter<'a, T: 'a> {
+    /// the metric the meter belongs to
+    pub channel: &'a T,
+    /// the labels of the samples counted by the meter
+    pub labels: &'a Labels,
+    /// the statistic held by the meter
+    pub kind: &'a MeterKind,
+    /// the value of the meter
+    pub reading: Reading,
+}
+
+impl<'a, T: Display> LabelledMeter<'a, T> {
+    /// the string representation of the meter without its labels, eg:
+    /// `ok_count`
+    pub fn name(&self) -> String {
+        format!("{}_{}", self.channel, self.kind)
+    }
+}
+
+// the readings of one labelled series
+type Readings<'a> = hash_map::Iter<'a, MeterKind, Reading>;
+
+/// an iterator over the `LabelledMeter`s in `Meters`
+pub struct LabelledIter<'a, T: 'a> {
+    series: hash_map::Iter<'a, (T, Labels), FnvHashMap<MeterKind, Reading>>,
+    current: Option<(&'a (T, Labels), Readings<'a>)>,
+}
+
+impl<'a, T> Iterator for LabelledIter<'a, T> {
+    type Item = LabelledMeter<'a, T>;
+
+    fn next(&mut self) -> Option<LabelledMeter<'a, T>> {
+        loop {
+            if let Some((series, ref mut kinds)) = self.current {
+                if let Some((kind, reading)) = kinds.next() {
+                    return Some(LabelledMeter {
+                        channel: &series.0,
+                        labels: &series.1,
+                        kind: kind,
+                        reading: *reading,
+                    });
+                }
+            }
+            match self.series.next() {
+                Some((series, kinds)) => self.current = Some((series, kinds.iter())),
+                None => return None,
+            }
+        }
+    }
+}
 
 /// `Meters` are the aggregated result of stats which
 /// have been processed by the `Receiver`.
 #[derive(Clone)]
 pub struct Meters<T> {
-    resource_type: PhantomData<T>,
-    /// a map of labels to their u64 values
-    pub data: FnvHashMap<String, u64>,
-    /// a map of labels to their f64 values
-    pub data_float: FnvHashMap<String, f64>,
+    data: FnvHashMap<T, FnvHashMap<MeterKind, Reading>>,
+    labelled: FnvHashMap<(T, Labels), FnvHashMap<MeterKind, Reading>>,
+    receiver: BTreeMap<String, u64>,
+    window: Window,
 }
 
 impl<T: Hash + Eq> Default for Meters<T> {
     fn default() -> Meters<T> {
         Meters {
             data: FnvHashMap::default(),
-            data_float: FnvHashMap::default(),
-            resource_type: PhantomData::<T>,
+            labelled: FnvHashMap::default(),
+            receiver: BTreeMap::new(),
+            window: Window::default(),
         }
     }
 }
@@ -33,62 +209,340 @@ impl<T: Hash + Eq + Send + Display + Clone> Meters<T> {
         Default::default()
     }
 
+    /// set the reading of the given kind for a metric
+    pub fn set(&mut self, channel: T, kind: MeterKind, reading: Reading) {
+        self.data.entry(channel).or_default().insert(
+            kind,
+            reading,
+        );
+    }
+
+    /// get the reading of the given kind for a metric
+    pub fn get(&self, channel: &T, kind: &MeterKind) -> Option<&Reading> {
+        self.data.get(channel).and_then(|kinds| kinds.get(kind))
+    }
+
+    /// set the reading of the given kind for one set of labels of a metric
+    pub fn set_labelled(&mut self, channel: T, labels: Labels, kind: MeterKind, reading: Reading) {
+        self.labelled
+            .entry((channel, labels))
+            .or_default()
+            .insert(kind, reading);
+    }
+
+    /// get the reading of the given kind for one set of labels of a metric
+    pub fn get_labelled(&self, channel: &T, labels: &Labels, kind: &MeterKind) -> Option<&Reading> {
+        self.labelled
+            .get(&(channel.clone(), labels.clone()))
+            .and_then(|kinds| kinds.get(kind))
+    }
+
+    /// remove all the readings for a metric, including those of its labels
+    pub fn remove(&mut self, channel: &T) {
+        self.data.remove(channel);
+        self.labelled.retain(|series, _| series.0 != *channel);
+    }
+
+    /// iterate over all the `Meter`s, which do not include the labelled
+    /// meters
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            channels: self.data.iter(),
+            current: None,
+        }
+    }
+
+    /// the integer readings keyed by the name of their meter, eg:
+    /// `ok_p50_nanoseconds`, as held by the former `data` field
+    pub fn data(&self) -> FnvHashMap<String, u64> {
+        self.iter()
+            .filter_map(|meter| match meter.reading {
+                Reading::Integer(value) => Some((meter.name(), value)),
+                Reading::Float(_) => None,
+            })
+            .collect()
+    }
+
+    /// the floating point readings keyed by the name of their meter, eg:
+    /// `ok_tau_1_adev`, as held by the former `data_float` field
+    pub fn data_float(&self) -> FnvHashMap<String, f64> {
+        self.iter()
+            .filter_map(|meter| match meter.reading {
+                Reading::Float(value) => Some((meter.name(), value)),
+                Reading::Integer(_) => None,
+            })
+            .collect()
+    }
+
+    /// iterate over all the `LabelledMeter`s
+    pub fn labelled(&self) -> LabelledIter<'_, T> {
+        LabelledIter {
+            series: self.labelled.iter(),
+            current: None,
+        }
+    }
+
     /// update the count of events for a given metric
     pub fn set_count(&mut self, channel: T, value: u64) {
-        let key = format!("{}_count", channel);
-        self.data.insert(key, value);
+        self.set(channel, MeterKind::Count, Reading::Integer(value));
     }
 
     /// update the value for a given metric
     pub fn set_value(&mut self, channel: T, value: u64) {
-        let key = format!("{}_value", channel);
-        self.data.insert(key, value);
+        self.set(channel, MeterKind::Value, Reading::Integer(value));
     }
 
     /// update the `Percentile` for a given metric
     pub fn set_latency_percentile(&mut self, channel: T, percentile: Percentile, value: u64) {
-        let key = format!("{}_{}_nanoseconds", channel, percentile.0);
-        self.data.insert(key, value);
+        let kind = MeterKind::LatencyPercentile(percentile.0);
+        self.set(channel, kind, Reading::Integer(value));
     }
 
     /// update the `Percentile` for a given metric
     pub fn set_value_percentile(&mut self, channel: T, percentile: Percentile, value: u64) {
-        let key = format!("{}_{}_units", channel, percentile.0);
-        self.data.insert(key, value);
+        let kind = MeterKind::ValuePercentile(percentile.0);
+        self.set(channel, kind, Reading::Integer(value));
+    }
+
+    /// update the lifetime `Percentile` for a given metric
+    pub fn set_latency_cumulative(&mut self, channel: T, percentile: Percentile, value: u64) {
+        let kind = MeterKind::LatencyCumulative(percentile.0);
+        self.set(channel, kind, Reading::Integer(value));
+    }
+
+    /// update the `Percentile` over the labelled horizon for a given metric
+    pub fn set_latency_rollup(
+        &mut self,
+        channel: T,
+        percentile: Percentile,
+        horizon: &str,
+        value: u64,
+    ) {
+        let kind = MeterKind::LatencyRollup(percentile.0, horizon.to_owned());
+        self.set(channel, kind, Reading::Integer(value));
+    }
+
+    /// update the `Percentile` over the labelled horizon for a given metric
+    pub fn set_value_rollup(
+        &mut self,
+        channel: T,
+        percentile: Percentile,
+        horizon: &str,
+        value: u64,
+    ) {
+        let kind = MeterKind::ValueRollup(percentile.0, horizon.to_owned());
+        self.set(channel, kind, Reading::Integer(value));
     }
 
     /// update the Allan Deviation for a given metric at a specific Tau
     pub fn set_adev(&mut self, channel: T, tau: usize, value: f64) {
-        let key = format!("{}_tau_{}_adev", channel, tau);
-        self.data_float.insert(key, value);
+        self.set(channel, MeterKind::Adev(tau), Reading::Float(value));
     }
 
     /// gets the count for a given metric
     pub fn count(&self, channel: &T) -> Option<&u64> {
-        let key = format!("{}_count", channel);
-        self.data.get(&key)
+        self.integer(channel, &MeterKind::Count)
+    }
+
+    /// gets the value for a given metric
+    pub fn value(&self, channel: &T) -> Option<&u64> {
+        self.integer(channel, &MeterKind::Value)
     }
 
     /// get a `Percentile` of sample latencies for a given metric
     pub fn latency_percentile(&self, channel: &T, percentile: Percentile) -> Option<&u64> {
-        let key = format!("{}_{}_nanoseconds", channel, percentile.0);
-        self.data.get(&key)
+        self.integer(channel, &MeterKind::LatencyPercentile(percentile.0))
     }
 
     /// get the `Percentile` of sample counts for a given metric
     pub fn value_percentile(&self, channel: &T, percentile: Percentile) -> Option<&u64> {
-        let key = format!("{}_{}_units", channel, percentile.0);
-        self.data.get(&key)
+        self.integer(channel, &MeterKind::ValuePercentile(percentile.0))
+    }
+
+    /// get a `Percentile` of sample latencies over the lifetime of the
+    /// `Receiver`
+    pub fn latency_cumulative(&self, channel: &T, percentile: Percentile) -> Option<&u64> {
+        self.integer(channel, &MeterKind::LatencyCumulative(percentile.0))
+    }
+
+    /// get a `Percentile` of sample latencies over the labelled horizon
+    pub fn latency_rollup(
+        &self,
+        channel: &T,
+        percentile: Percentile,
+        horizon: &str,
+    ) -> Option<&u64> {
+        self.integer(
+            channel,
+            &MeterKind::LatencyRollup(percentile.0, horizon.to_owned()),
+        )
+    }
+
+    /// get a `Percentile` of sample counts over the labelled horizon
+    pub fn value_rollup(&self, channel: &T, percentile: Percentile, horizon: &str) -> Option<&u64> {
+        self.integer(
+            channel,
+            &MeterKind::ValueRollup(percentile.0, horizon.to_owned()),
+        )
     }
 
     /// get the Allan Deviation for the channel for a given Tau
     pub fn adev(&self, channel: T, tau: usize) -> Option<&f64> {
-        let key = format!("{}_tau_{}_adev", channel, tau);
-        self.data_float.get(&key)
+        match self.get(&channel, &MeterKind::Adev(tau)) {
+            Some(Reading::Float(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// the `Window` the `Meters` were calculated for
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    /// set the `Window` the `Meters` were calculated for
+    pub fn set_window(&mut self, window: Window) {
+        self.window = window;
+    }
+
+    /// set a meter of the `Receiver` itself rather than of a metric, eg:
+    /// `tic_artifact_errors`
+    pub fn set_receiver_meter(&mut self, name: &str, value: u64) {
+        self.receiver.insert(name.to_owned(), value);
+    }
+
+    /// get a meter of the `Receiver` itself
+    pub fn receiver_meter(&self, name: &str) -> Option<&u64> {
+        self.receiver.get(name)
+    }
+
+    /// iterate over the meters of the `Receiver` itself, in order of name
+    pub fn receiver_meters(&self) -> btree_map::Iter<'_, String, u64> {
+        self.receiver.iter()
     }
 
-    /// clear the Meters
+    /// clear the readings of the Meters, the `Window` is unchanged
     pub fn clear(&mut self) {
-        self.data.clear()
+        self.data.clear();
+        self.labelled.clear();
+        self.receiver.clear();
+    }
+
+    fn integer(&self, channel: &T, kind: &MeterKind) -> Option<&u64> {
+        match self.get(channel, kind) {
+            Some(Reading::Integer(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structured() {
+        let mut meters = Meters::<String>::new();
+        let ok = "ok".to_owned();
+        meters.set_count(ok.clone(), 1);
+        meters.set_value(ok.clone(), 2);
+        meters.set_latency_percentile(ok.clone(), Percentile("p50".to_owned(), 50.0), 3);
+        meters.set_value_percentile(ok.clone(), Percentile("p50".to_owned(), 50.0), 4);
+        meters.set_adev(ok.clone(), 1, 0.5);
+        meters.set_latency_rollup(ok.clone(), Percentile("p99".to_owned(), 99.0), "5m", 5);
+
+        assert_eq!(meters.count(&ok), Some(&1));
+        assert_eq!(meters.value(&ok), Some(&2));
+        assert_eq!(
+            meters.latency_percentile(&ok, Percentile("p50".to_owned(), 50.0)),
+            Some(&3)
+        );
+        assert_eq!(
+            meters.value_percentile(&ok, Percentile("p50".to_owned(), 50.0)),
+            Some(&4)
+        );
+        assert_eq!(meters.adev(ok.clone(), 1), Some(&0.5));
+        assert_eq!(
+            meters.latency_rollup(&ok, Percentile("p99".to_owned(), 99.0), "5m"),
+            Some(&5)
+        );
+        assert_eq!(
+            meters.latency_rollup(&ok, Percentile("p99".to_owned(), 99.0), "1h"),
+            None
+        );
+        let name = format!(
+            "{}",
+            MeterKind::LatencyRollup("p99".to_owned(), "5m".to_owned())
+        );
+        assert_eq!(name, "p99_nanoseconds_5m");
+        assert_eq!(meters.count(&"missing".to_owned()), None);
+
+        let data = meters.data();
+        assert_eq!(data.len(), 5);
+        assert_eq!(data.get("ok_count"), Some(&1));
+        assert_eq!(data.get("ok_p50_nanoseconds"), Some(&3));
+        assert_eq!(meters.data_float().get("ok_tau_1_adev"), Some(&0.5));
+        assert_eq!(meters.data_float().len(), 1);
+    }
+
+    #[test]
+    fn no_collisions() {
+        // a metric named `ok_p50` must not clash with the p50 of `ok`
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok_p50".to_owned(), 1);
+        meters.set_latency_percentile("ok".to_owned(), Percentile("p50".to_owned(), 50.0), 2);
+        assert_eq!(meters.count(&"ok_p50".to_owned()), Some(&1));
+        assert_eq!(meters.count(&"ok".to_owned()), None);
+    }
+
+    #[test]
+    fn iterate() {
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 1);
+        meters.set_adev("ok".to_owned(), 10, 0.5);
+        meters.set_value("total".to_owned(), 2);
+
+        let mut names: Vec<(String, String)> = meters
+            .iter()
+            .map(|m| (m.name(), format!("{}", m.reading)))
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                ("ok_count".to_owned(), "1".to_owned()),
+                ("ok_tau_10_adev".to_owned(), "0.5".to_owned()),
+                ("total_value".to_owned(), "2".to_owned()),
+            ]
+        );
+
+        meters.clear();
+        assert_eq!(meters.iter().count(), 0);
+    }
+
+    #[test]
+    fn labelled() {
+        let mut meters = Meters::<String>::new();
+        let ok = "ok".to_owned();
+        let error = Labels::new().with("status", "500");
+        meters.set_count(ok.clone(), 3);
+        meters.set_labelled(ok.clone(), error.clone(), MeterKind::Count, Reading::Integer(1));
+
+        assert_eq!(meters.iter().count(), 1);
+        assert_eq!(
+            meters.get_labelled(&ok, &error, &MeterKind::Count),
+            Some(&Reading::Integer(1))
+        );
+        assert_eq!(meters.get_labelled(&ok, &Labels::new(), &MeterKind::Count), None);
+        let labelled: Vec<(String, String)> = meters
+            .labelled()
+            .map(|m| (m.name(), m.labels.to_string()))
+            .collect();
+        assert_eq!(
+            labelled,
+            vec![("ok_count".to_owned(), "status=\"500\"".to_owned())]
+        );
+
+        meters.remove(&ok);
+        assert_eq!(meters.labelled().count(), 0);
     }
 }
@@ -3,7 +3,9 @@ mod counters;
 mod gauges;
 mod heatmaps;
 mod histograms;
+mod labels;
 mod meters;
+mod rollups;
 mod sample;
 
 pub use self::allans::Allans;
@@ -11,5 +13,10 @@ pub use self::counters::Counters;
 pub use self::gauges::Gauges;
 pub use self::heatmaps::Heatmaps;
 pub use self::histograms::Histograms;
-pub use self::meters::Meters;
+pub use self::labels::Labels;
+pub use self::labels::escape as escape_label;
+pub use self::meters::{Iter as MetersIter, LabelledIter, LabelledMeter, Meter, MeterKind, Meters,
+                       Reading};
+pub use self::rollups::Rollups;
+pub use self::rollups::label as horizon_label;
 pub use self::sample::Sample;
@@ -0,0 +1,218 @@
+// `Rollups` merge the per-window histograms of each metric over longer
+// horizons, keyed by metric
+
+use fnv::FnvHashMap;
+use histogram::{self, Histogram};
+use std::collections::VecDeque;
+use std::hash::Hash;
+
+/// the label for a horizon of the given number of seconds, eg: `5m`
+pub fn label(seconds: usize) -> String {
+    if seconds > 0 && seconds % 3600 == 0 {
+        format!("{}h", seconds / 3600)
+    } else if seconds > 0 && seconds % 60 == 0 {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// a rolling histogram which covers a fixed number of windows
+struct Rollup {
+    windows: usize,
+    histogram: Histogram,
+}
+
+// the non-empty buckets of each recent window and the rollups built from them
+struct Series {
+    windows: VecDeque<Vec<(u64, u64)>>,
+    rollups: Vec<Rollup>,
+}
+
+pub struct Rollups<T> {
+    config: histogram::Config,
+    horizons: Vec<(String, usize)>,
+    data: FnvHashMap<T, Series>,
+}
+
+impl<T: Hash + Eq> Rollups<T> {
+    /// create `Rollups` for the given horizons, each a label and the number
+    /// of windows it spans, whose histograms are built from the config
+    pub fn new(config: histogram::Config, horizons: Vec<(String, usize)>) -> Rollups<T> {
+        Rollups {
+            config: config,
+            horizons: horizons,
+            data: FnvHashMap::default(),
+        }
+    }
+
+    pub fn horizons(&self) -> &[(String, usize)] {
+        &self.horizons
+    }
+
+    pub fn init(&mut self, key: T) {
+        if self.horizons.is_empty() {
+            return;
+        }
+        let rollups = self.horizons
+            .iter()
+            .map(|&(_, windows)| {
+                Rollup {
+                    windows: windows,
+                    histogram: self.config.build().unwrap(),
+                }
+            })
+            .collect();
+        self.data.insert(
+            key,
+            Series {
+                windows: VecDeque::new(),
+                rollups: rollups,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, key: &T) {
+        self.data.remove(key);
+    }
+
+    /// add the histogram of a window which has just closed, any windows which
+    /// have fallen out of a horizon are removed from its rollup. returns the
+    /// number of samples which the rollups could not hold
+    pub fn record(&mut self, key: &T, window: &Histogram) -> u64 {
+        let mut dropped = 0;
+        if let Some(series) = self.data.get_mut(key) {
+            let buckets: Vec<(u64, u64)> = window
+                .into_iter()
+                .filter(|b| b.count() > 0)
+                .map(|b| (b.value(), b.count()))
+                .collect();
+            series.windows.push_back(buckets);
+            let len = series.windows.len();
+            let mut max = 0;
+            for rollup in &mut series.rollups {
+                for &(value, count) in &series.windows[len - 1] {
+                    if rollup.histogram.increment_by(value, count).is_err() {
+                        dropped += count;
+                    }
+                }
+                // dropped samples are removed from the count of misses alike
+                if len > rollup.windows {
+                    for &(value, count) in &series.windows[len - 1 - rollup.windows] {
+                        let _ = rollup.histogram.decrement_by(value, count);
+                    }
+                }
+                if rollup.windows > max {
+                    max = rollup.windows;
+                }
+            }
+            while series.windows.len() > max {
+                series.windows.pop_front();
+            }
+        }
+        dropped
+    }
+
+    pub fn entries(&self, key: &T, horizon: usize) -> u64 {
+        match self.data.get(key) {
+            Some(series) => series.rollups[horizon].histogram.entries(),
+            None => 0,
+        }
+    }
+
+    pub fn percentile(
+        &self,
+        key: &T,
+        horizon: usize,
+        percentile: f64,
+    ) -> Result<u64, &'static str> {
+        match self.data.get(key) {
+            Some(series) => series.rollups[horizon].histogram.percentile(percentile),
+            None => Err("no data"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE_MINUTE: u64 = 60_000_000_000;
+
+    fn config() -> histogram::Config {
+        Histogram::configure().max_value(ONE_MINUTE)
+    }
+
+    fn window(values: &[u64]) -> Histogram {
+        let mut histogram = config().build().unwrap();
+        for value in values {
+            histogram.increment(*value).unwrap();
+        }
+        histogram
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(label(30), "30s");
+        assert_eq!(label(60), "1m");
+        assert_eq!(label(300), "5m");
+        assert_eq!(label(3600), "1h");
+        assert_eq!(label(5400), "90m");
+    }
+
+    #[test]
+    fn rolling() {
+        let horizons = vec![("2s".to_owned(), 2), ("3s".to_owned(), 3)];
+        let mut rollups = Rollups::<usize>::new(config(), horizons);
+        rollups.init(1);
+
+        assert_eq!(rollups.record(&1, &window(&[100, 100])), 0);
+        rollups.record(&1, &window(&[200]));
+        assert_eq!(rollups.entries(&1, 0), 3);
+        assert_eq!(rollups.percentile(&1, 0, 100.0).unwrap(), 200);
+
+        // the first window falls out of the shorter horizon only
+        rollups.record(&1, &window(&[300]));
+        assert_eq!(rollups.entries(&1, 0), 2);
+        assert_eq!(rollups.percentile(&1, 0, 0.0).unwrap(), 200);
+        assert_eq!(rollups.entries(&1, 1), 4);
+        assert_eq!(rollups.percentile(&1, 1, 0.0).unwrap(), 100);
+        // of 100, 100, 200 and 300 the histogram reports the p50 as 200, the
+        // largest value with at least half of the samples at or above it
+        assert_eq!(rollups.percentile(&1, 1, 50.0).unwrap(), 200);
+
+        rollups.record(&1, &window(&[]));
+        rollups.record(&1, &window(&[]));
+        rollups.record(&1, &window(&[]));
+        assert_eq!(rollups.entries(&1, 0), 0);
+        assert_eq!(rollups.entries(&1, 1), 0);
+    }
+
+    #[test]
+    fn no_horizons() {
+        let mut rollups = Rollups::<usize>::new(config(), Vec::new());
+        rollups.init(1);
+        rollups.record(&1, &window(&[100]));
+        assert_eq!(rollups.entries(&1, 0), 0);
+    }
+
+    #[test]
+    fn dropped() {
+        let horizons = vec![("2s".to_owned(), 2), ("3s".to_owned(), 3)];
+        let config = Histogram::configure().max_value(1_000);
+        let mut rollups = Rollups::<usize>::new(config, horizons);
+        rollups.init(1);
+
+        // the sample above the max value is dropped by each of the rollups,
+        // which still count it, and is removed again with its window
+        assert_eq!(rollups.record(&1, &window(&[100, 100_000])), 2);
+        assert_eq!(rollups.entries(&1, 0), 2);
+        assert_eq!(rollups.record(&1, &window(&[200])), 0);
+        assert_eq!(rollups.percentile(&1, 0, 0.0).unwrap(), 100);
+        for _ in 0..3 {
+            rollups.record(&1, &window(&[]));
+        }
+        assert_eq!(rollups.entries(&1, 0), 0);
+        assert_eq!(rollups.entries(&1, 1), 0);
+    }
+}
@@ -1,3 +1,4 @@
+use data::Labels;
 use std::fmt::Display;
 use std::hash::Hash;
 
@@ -9,6 +10,7 @@ pub struct Sample<T> {
     count: u64,
     value: u64,
     channel: T,
+    labels: Labels,
 }
 
 impl<T: Hash + Eq + Send + Display + Clone> Sample<T> {
@@ -20,6 +22,7 @@ impl<T: Hash + Eq + Send + Display + Clone> Sample<T> {
             count: 1,
             value: 0,
             channel: channel,
+            labels: Labels::new(),
         }
     }
 
@@ -31,6 +34,7 @@ impl<T: Hash + Eq + Send + Display + Clone> Sample<T> {
             count: count,
             value: 0,
             channel: channel,
+            labels: Labels::new(),
         }
     }
 
@@ -42,9 +46,33 @@ impl<T: Hash + Eq + Send + Display + Clone> Sample<T> {
             count: 1,
             value: value,
             channel: channel,
+            labels: Labels::new(),
         }
     }
 
+    /// add a label to the sample, the counts and latencies of each set of
+    /// labels are reported separately for metrics with a `Count` or
+    /// `LatencyPercentile` interest, in addition to those of the metric
+    ///
+    /// samples without labels hold an empty `Labels`, which does not allocate,
+    /// and the receiver skips the labelled series for them entirely
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Sample;
+    /// let sample = Sample::new(0, 100, "request").with_label("status", "500");
+    /// assert_eq!(sample.labels().get("status"), Some("500"));
+    /// ```
+    pub fn with_label(mut self, key: &str, value: &str) -> Self {
+        self.labels.insert(key, value);
+        self
+    }
+
+    /// return the labels
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
     /// return the metric /// deprecated
     pub fn metric(&self) -> T {
         self.channel.clone()
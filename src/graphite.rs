@@ -0,0 +1,314 @@
+// `Graphite` pushes `Meters` to a carbon receiver over TCP
+
+use common::SECOND;
+use data::{Meters, Reading};
+use sink::{Sink, Window};
+use std::cmp;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// the wire protocol used by the `Graphite` exporter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    /// newline separated `path value timestamp` lines, usually port 2003
+    Plaintext,
+    /// length-prefixed python pickles, usually port 2004
+    Pickle,
+}
+
+/// a `Graphite` exporter is a `Sink` which pushes the `Meters` to carbon at
+/// the end of each window, timestamped with the end time of the window
+///
+/// if the connection fails, the exporter drops the data for the window and
+/// reconnects after an exponentially increasing backoff
+pub struct Graphite {
+    address: SocketAddr,
+    prefix: String,
+    protocol: Protocol,
+    timeout: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+    stream: Option<TcpStream>,
+}
+
+impl Clone for Graphite {
+    /// clones share the configuration, but not the connection
+    fn clone(&self) -> Graphite {
+        Graphite {
+            address: self.address,
+            prefix: self.prefix.clone(),
+            protocol: self.protocol,
+            timeout: self.timeout,
+            min_backoff: self.min_backoff,
+            max_backoff: self.max_backoff,
+            backoff: self.min_backoff,
+            retry_at: None,
+            stream: None,
+        }
+    }
+}
+
+impl Graphite {
+    /// create a new `Graphite` exporter which connects to the given address
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Graphite;
+    /// let graphite = Graphite::new("127.0.0.1:2003").prefix("servers.web01");
+    /// ```
+    pub fn new<U: ToSocketAddrs>(address: U) -> Graphite {
+        let address = address
+            .to_socket_addrs()
+            .expect("SocketAddr lookup failed")
+            .next()
+            .expect("SocketAddr resolved to empty set");
+
+        Graphite {
+            address: address,
+            prefix: "".to_owned(),
+            protocol: Protocol::Plaintext,
+            timeout: Duration::from_secs(1),
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            backoff: Duration::from_secs(1),
+            retry_at: None,
+            stream: None,
+        }
+    }
+
+    /// set the prefix prepended to each metric path: default none
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// set the wire protocol: default `Protocol::Plaintext`
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// set the connect and write timeout: default 1s
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// set the range of the reconnect backoff: default 1s to 60s
+    ///
+    /// the backoff doubles after each failed attempt and resets once a
+    /// window has been sent successfully
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = cmp::max(min, max);
+        self.backoff = min;
+        self
+    }
+
+    /// send the `Meters` to carbon with the given timestamp, in seconds
+    /// since the unix epoch
+    pub fn emit<T>(&mut self, meters: &Meters<T>, timestamp: u64) -> Result<(), io::Error>
+    where
+        T: Hash + Eq + Send + Display + Clone,
+    {
+        if let Some(retry_at) = self.retry_at {
+            if Instant::now() < retry_at {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "waiting to reconnect to graphite",
+                ));
+            }
+        }
+
+        let mut points: Vec<(String, Reading)> = meters
+            .iter()
+            .map(|meter| (self.path(&meter.name()), meter.reading))
+            .collect();
+        for (name, value) in meters.receiver_meters() {
+            points.push((self.path(name), Reading::Integer(*value)));
+        }
+        let payload = match self.protocol {
+            Protocol::Plaintext => plaintext(&points, timestamp),
+            Protocol::Pickle => pickle(&points, timestamp),
+        };
+
+        match self.send(&payload) {
+            Ok(()) => {
+                self.backoff = self.min_backoff;
+                self.retry_at = None;
+                Ok(())
+            }
+            Err(e) => {
+                self.stream = None;
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = cmp::min(self.backoff * 2, self.max_backoff);
+                Err(e)
+            }
+        }
+    }
+
+    // write the payload, connecting first if required
+    fn send(&mut self, payload: &[u8]) -> Result<(), io::Error> {
+        if self.stream.is_none() {
+            let stream = TcpStream::connect_timeout(&self.address, self.timeout)?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            stream.set_nodelay(true)?;
+            self.stream = Some(stream);
+        }
+        let stream = self.stream.as_mut().unwrap();
+        stream.write_all(payload)?;
+        stream.flush()
+    }
+
+    // the full graphite path for the named meter
+    fn path(&self, stat: &str) -> String {
+        let stat = stat.replace(|c: char| c.is_whitespace(), "_");
+        if self.prefix.is_empty() {
+            stat
+        } else {
+            format!("{}.{}", self.prefix, stat)
+        }
+    }
+}
+
+impl<T: Hash + Eq + Send + Display + Clone> Sink<T> for Graphite {
+    fn window(&mut self, window: &Window, meters: &Meters<T>) -> Result<(), io::Error> {
+        match self.emit(meters, window.end / SECOND) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                debug!("skipped graphite: {}", e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+// encode the points in the plaintext protocol
+fn plaintext(points: &[(String, Reading)], timestamp: u64) -> Vec<u8> {
+    let mut output = String::new();
+    for &(ref path, value) in points {
+        output.push_str(&format!("{} {} {}\n", path, value, timestamp));
+    }
+    output.into_bytes()
+}
+
+// encode the points as a pickled list of `(path, (timestamp, value))` tuples
+// using pickle protocol 2, prefixed with the big-endian payload length
+fn pickle(points: &[(String, Reading)], timestamp: u64) -> Vec<u8> {
+    let mut body = vec![0x80, 0x02, b']', b'('];
+    for &(ref path, value) in points {
+        body.push(b'X');
+        body.extend_from_slice(&le_u32(path.len() as u32));
+        body.extend_from_slice(path.as_bytes());
+        body.push(b'G');
+        body.extend_from_slice(&be_f64(timestamp as f64));
+        body.push(b'G');
+        body.extend_from_slice(&be_f64(value.as_f64()));
+        body.push(0x86); // TUPLE2: (timestamp, value)
+        body.push(0x86); // TUPLE2: (path, (timestamp, value))
+    }
+    body.push(b'e'); // APPENDS
+    body.push(b'.'); // STOP
+
+    let length = body.len() as u32;
+    let mut output = vec![
+        (length >> 24) as u8,
+        (length >> 16) as u8,
+        (length >> 8) as u8,
+        length as u8,
+    ];
+    output.extend_from_slice(&body);
+    output
+}
+
+fn le_u32(value: u32) -> [u8; 4] {
+    [
+        value as u8,
+        (value >> 8) as u8,
+        (value >> 16) as u8,
+        (value >> 24) as u8,
+    ]
+}
+
+fn be_f64(value: f64) -> [u8; 8] {
+    let bits = value.to_bits();
+    let mut output = [0; 8];
+    for (i, byte) in output.iter_mut().enumerate() {
+        *byte = (bits >> (56 - 8 * i)) as u8;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn plaintext_format() {
+        let points = vec![
+            ("tic.ok_count".to_owned(), Reading::Integer(10)),
+            ("tic.ok_tau_1_adev".to_owned(), Reading::Float(0.5)),
+        ];
+        assert_eq!(
+            plaintext(&points, 1500000000),
+            b"tic.ok_count 10 1500000000\ntic.ok_tau_1_adev 0.5 1500000000\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn pickle_format() {
+        let points = vec![("a".to_owned(), Reading::Integer(1))];
+        let output = pickle(&points, 2);
+        let mut expected = vec![0x80, 0x02, b']', b'(', b'X', 1, 0, 0, 0, b'a', b'G'];
+        expected.extend_from_slice(&[0x40, 0, 0, 0, 0, 0, 0, 0]);
+        expected.push(b'G');
+        expected.extend_from_slice(&[0x3F, 0xF0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[0x86, 0x86, b'e', b'.']);
+        assert_eq!(&output[..4], &[0, 0, 0, expected.len() as u8]);
+        assert_eq!(&output[4..], &expected[..]);
+    }
+
+    #[test]
+    fn send_plaintext() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut graphite = Graphite::new(listener.local_addr().unwrap()).prefix("tic");
+
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 10);
+        graphite.emit(&meters, 1500000000).unwrap();
+        drop(graphite);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "tic.ok_count 10 1500000000\n");
+    }
+
+    #[test]
+    fn backoff_after_failure() {
+        // bind and drop a listener to find a port which refuses connections
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut graphite = Graphite::new(address).backoff(
+            Duration::from_secs(60),
+            Duration::from_secs(120),
+        );
+
+        let meters = Meters::<String>::new();
+        assert!(graphite.emit(&meters, 0).is_err());
+        assert_eq!(
+            graphite.emit(&meters, 0).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(graphite.backoff, Duration::from_secs(120));
+    }
+}
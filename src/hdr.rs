@@ -0,0 +1,388 @@
+// `HdrLog` writes latency histograms as an HdrHistogram interval log
+
+use common::SECOND;
+use data::Meters;
+use histogram::Histogram;
+use sink::{Sink, Window, WindowHistograms};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// cookies of the V2 encoding, with the word size nibble set as by HdrHistogram
+const ENCODING_COOKIE: u32 = 0x1c84_9313;
+const COMPRESSED_ENCODING_COOKIE: u32 = 0x1c84_9314;
+
+// the precision and range of the encoded histograms
+const SIGNIFICANT_DIGITS: u32 = 3;
+const HIGHEST_TRACKABLE_VALUE: u64 = 60 * SECOND;
+
+/// an `HdrLog` is a `Sink` which writes an HdrHistogram interval log, as read
+/// by tools such as HistogramLogAnalyzer
+///
+/// each window adds one line per latency metric, tagged with the metric name,
+/// with the start time relative to the first window and the length of the
+/// window in seconds, the max latency in milliseconds and the V2 compressed
+/// histogram in base64
+pub struct HdrLog {
+    path: PathBuf,
+    file: Option<File>,
+    base_time: Option<u64>,
+}
+
+impl HdrLog {
+    /// create an `HdrLog` which writes to the given file, the file is
+    /// truncated when the first window is written
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::HdrLog;
+    /// let log = HdrLog::new("latency.hlog");
+    /// ```
+    pub fn new<P: AsRef<Path>>(path: P) -> HdrLog {
+        HdrLog {
+            path: path.as_ref().to_path_buf(),
+            file: None,
+            base_time: None,
+        }
+    }
+
+    // write the lines, opening the file and writing the header if required
+    fn write(&mut self, start: u64, lines: &str) -> Result<(), io::Error> {
+        if self.file.is_none() {
+            let file = match self.base_time {
+                Some(_) => OpenOptions::new().append(true).open(&self.path)?,
+                None => {
+                    let mut file = File::create(&self.path)?;
+                    file.write_all(header(start).as_bytes())?;
+                    self.base_time = Some(start);
+                    file
+                }
+            };
+            self.file = Some(file);
+        }
+        let result = {
+            let file = self.file.as_mut().unwrap();
+            file.write_all(lines.as_bytes()).and_then(|_| file.flush())
+        };
+        if result.is_err() {
+            self.file = None;
+        }
+        result
+    }
+}
+
+impl<T: Hash + Eq + Send + Display + Clone> Sink<T> for HdrLog {
+    fn window(&mut self, _: &Window, _: &Meters<T>) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn histograms(
+        &mut self,
+        window: &Window,
+        histograms: &WindowHistograms<T>,
+    ) -> Result<(), io::Error> {
+        let base_time = self.base_time.unwrap_or(window.start);
+        let mut lines = String::new();
+        let mut latencies: Vec<(String, &Histogram)> = histograms
+            .latency_histograms()
+            .into_iter()
+            .map(|(key, histogram)| (key.to_string(), histogram))
+            .collect();
+        latencies.sort_by(|a, b| a.0.cmp(&b.0));
+        for (metric, histogram) in latencies {
+            let counts = counts(histogram);
+            let max = histogram.maximum().unwrap_or(0);
+            lines.push_str(&format!(
+                "Tag={},{:.3},{:.3},{:.3},{}\n",
+                tag(&metric),
+                window.start.saturating_sub(base_time) as f64 / SECOND as f64,
+                window.duration as f64 / SECOND as f64,
+                max as f64 / 1_000_000.0,
+                base64(&compress(&encode(&counts)))
+            ));
+        }
+        self.write(window.start, &lines)
+    }
+}
+
+// the header of the log, with the start time in seconds since the epoch
+fn header(start: u64) -> String {
+    let seconds = start as f64 / SECOND as f64;
+    format!(
+        "#[Histogram log format version 1.3]\n\
+         #[StartTime: {:.3} (seconds since epoch)]\n\
+         #[BaseTime: {:.3} (seconds since epoch)]\n\
+         \"StartTimestamp\",\"Interval_Length\",\"Interval_Max\",\
+         \"Interval_Compressed_Histogram\"\n",
+        seconds,
+        seconds
+    )
+}
+
+// tags may not contain the separators of the log
+fn tag(metric: &str) -> String {
+    metric.replace(|c: char| c == ',' || c.is_whitespace(), "_")
+}
+
+// the index of the HdrHistogram counts array which holds the value
+fn counts_index(value: u64) -> usize {
+    // with a lowest discernible value of 1, 3 significant digits gives 2048
+    // sub-buckets per bucket
+    let sub_bucket_half_count_magnitude = 10;
+    let sub_bucket_half_count = 1 << sub_bucket_half_count_magnitude;
+    let sub_bucket_mask = (sub_bucket_half_count << 1) - 1;
+    let leading_zero_count_base = 64 - sub_bucket_half_count_magnitude - 1;
+
+    let bucket_index = leading_zero_count_base - (value | sub_bucket_mask).leading_zeros() as u64;
+    let sub_bucket_index = value >> bucket_index;
+    (((bucket_index + 1) << sub_bucket_half_count_magnitude) + sub_bucket_index -
+         sub_bucket_half_count) as usize
+}
+
+// the HdrHistogram counts array of the histogram, up to the largest value
+fn counts(histogram: &Histogram) -> Vec<u64> {
+    let mut counts = Vec::new();
+    for bucket in histogram {
+        if bucket.count() == 0 {
+            continue;
+        }
+        let value = if bucket.value() > HIGHEST_TRACKABLE_VALUE {
+            HIGHEST_TRACKABLE_VALUE
+        } else {
+            bucket.value()
+        };
+        let index = counts_index(value);
+        if index >= counts.len() {
+            counts.resize(index + 1, 0);
+        }
+        counts[index] += bucket.count();
+    }
+    counts
+}
+
+// the uncompressed V2 encoding of the counts array
+fn encode(counts: &[u64]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    let mut i = 0;
+    while i < counts.len() {
+        if counts[i] == 0 {
+            // runs of zeros are written as a negative count
+            let mut zeros = 0;
+            while i < counts.len() && counts[i] == 0 {
+                zeros += 1;
+                i += 1;
+            }
+            put_zigzag(&mut payload, if zeros > 1 { -zeros } else { 0 });
+        } else {
+            put_zigzag(&mut payload, counts[i] as i64);
+            i += 1;
+        }
+    }
+
+    let mut output = Vec::with_capacity(40 + payload.len());
+    output.extend_from_slice(&be_u32(ENCODING_COOKIE));
+    output.extend_from_slice(&be_u32(payload.len() as u32));
+    output.extend_from_slice(&be_u32(0)); // normalizing index offset
+    output.extend_from_slice(&be_u32(SIGNIFICANT_DIGITS));
+    output.extend_from_slice(&be_u64(1)); // lowest discernible value
+    output.extend_from_slice(&be_u64(HIGHEST_TRACKABLE_VALUE));
+    output.extend_from_slice(&be_u64(1.0_f64.to_bits())); // conversion ratio
+    output.extend_from_slice(&payload);
+    output
+}
+
+// wrap the encoding in the compressed V2 format. the zlib stream uses stored
+// blocks, which any inflater accepts, as the encoding is already compact
+fn compress(encoding: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = encoding.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        zlib.push(if last { 0x01 } else { 0x00 });
+        zlib.extend_from_slice(&[len as u8, (len >> 8) as u8]);
+        zlib.extend_from_slice(&[!len as u8, (!len >> 8) as u8]);
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&be_u32(adler32(encoding)));
+
+    let mut output = Vec::with_capacity(8 + zlib.len());
+    output.extend_from_slice(&be_u32(COMPRESSED_ENCODING_COOKIE));
+    output.extend_from_slice(&be_u32(zlib.len() as u32));
+    output.extend_from_slice(&zlib);
+    output
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65_521;
+        b = (b + a) % 65_521;
+    }
+    (b << 16) | a
+}
+
+// append a zigzag LEB128 varint
+fn put_zigzag(output: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        output.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn be_u32(value: u32) -> [u8; 4] {
+    [
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]
+}
+
+fn be_u64(value: u64) -> [u8; 8] {
+    let mut output = [0; 8];
+    for (i, byte) in output.iter_mut().enumerate() {
+        *byte = (value >> (56 - 8 * i)) as u8;
+    }
+    output
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+    for chunk in input.chunks(3) {
+        let n = (u32::from(chunk[0]) << 16) |
+            (u32::from(*chunk.get(1).unwrap_or(&0)) << 8) |
+            u32::from(*chunk.get(2).unwrap_or(&0));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::Histograms;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn indexes() {
+        assert_eq!(counts_index(0), 0);
+        assert_eq!(counts_index(5), 5);
+        assert_eq!(counts_index(2047), 2047);
+        // above 2048 each bucket halves the resolution
+        assert_eq!(counts_index(2048), 2048);
+        assert_eq!(counts_index(2049), 2048);
+        assert_eq!(counts_index(4096), 3072);
+    }
+
+    #[test]
+    fn encoding() {
+        let encoding = encode(&[0, 0, 0, 2, 1]);
+        assert_eq!(&encoding[..4], &[0x1c, 0x84, 0x93, 0x13]);
+        assert_eq!(&encoding[4..8], &[0, 0, 0, 3]);
+        // three zeros, then the counts 2 and 1 zigzag encoded
+        assert_eq!(&encoding[40..], &[5, 4, 2]);
+    }
+
+    #[test]
+    fn compressed() {
+        let output = compress(b"abc");
+        assert_eq!(&output[..4], &[0x1c, 0x84, 0x93, 0x14]);
+        assert_eq!(&output[4..8], &[0, 0, 0, 14]);
+        assert_eq!(
+            &output[8..],
+            &[0x78, 0x01, 0x01, 3, 0, 0xFC, 0xFF, b'a', b'b', b'c', 0x02, 0x4D, 0x01, 0x27]
+        );
+        // the prefix all interval logs share
+        assert!(base64(&output).starts_with("HISTFAAAAA"));
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+
+    // the fields of an interval line: tag, start, length, max and histogram
+    fn fields(line: &str) -> Vec<&str> {
+        line.split(',').collect()
+    }
+
+    #[test]
+    fn interval_log() {
+        let path = env::temp_dir().join(format!("tic_hdr_{}.hlog", process::id()));
+        let mut log = HdrLog::new(&path);
+        let mut latency = Histograms::<String>::new();
+        let value = Histograms::<String>::new();
+        latency.init("ok".to_owned());
+        latency.init("error".to_owned());
+
+        let start = 1_500_000_000 * SECOND;
+        let mut encoded = Vec::new();
+        for (index, sample) in [1_000_000, 2_500_000].iter().enumerate() {
+            latency.clear();
+            latency.increment("ok".to_owned(), *sample);
+            let window = Window {
+                index: index,
+                start: start + index as u64 * SECOND,
+                duration: SECOND,
+                ..Default::default()
+            };
+            let histograms = WindowHistograms::new(&latency, &value);
+            Sink::histograms(&mut log, &window, &histograms).unwrap();
+            let histogram = &latency.data["ok"];
+            encoded.push(base64(&compress(&encode(&counts(histogram)))));
+        }
+        drop(log);
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(
+            &lines[..4],
+            &[
+                "#[Histogram log format version 1.3]",
+                "#[StartTime: 1500000000.000 (seconds since epoch)]",
+                "#[BaseTime: 1500000000.000 (seconds since epoch)]",
+                "\"StartTimestamp\",\"Interval_Length\",\"Interval_Max\",\
+                 \"Interval_Compressed_Histogram\"",
+            ]
+        );
+
+        // each window has a line per metric, sorted by tag, with the start
+        // relative to the first window
+        for (window, pair) in lines[4..].chunks(2).enumerate() {
+            let start = format!("{}.000", window);
+            let error = fields(pair[0]);
+            assert_eq!(&error[..4], &["Tag=error", start.as_str(), "1.000", "0.000"]);
+            assert!(error[4].starts_with("HISTFAAAA"));
+
+            let ok = fields(pair[1]);
+            assert_eq!(&ok[..3], &["Tag=ok", start.as_str(), "1.000"]);
+            assert_eq!(ok[4], encoded[window]);
+        }
+        // the max is given in milliseconds
+        let max: f64 = fields(lines[5])[3].parse().unwrap();
+        assert!((max - 1.0).abs() < 0.01);
+        let max: f64 = fields(lines[7])[3].parse().unwrap();
+        assert!((max - 2.5).abs() < 0.01);
+    }
+}
@@ -1,11 +1,54 @@
 use controller::Controller;
+use data::{self, Labels, MeterKind, Meters, Reading};
+use influx;
 use receiver::Receiver;
 use std::fmt::Display;
 use std::hash::Hash;
+use std::io::{self, Cursor};
 use std::net::ToSocketAddrs;
-use tiny_http::{Response, Server};
+use tiny_http::{Header, Response, Server};
 
 /// an HTTP-based endpoint for viewing all registered metrics on a `Receiver`
+///
+/// the metrics endpoints accept query parameters to limit the output:
+///
+/// * `prefix=ok_` - only include meters whose name starts with the prefix
+/// * `metric=ok` - only include meters belonging to the named metric
+/// * `type=percentile` - only include meters of the given type, one of:
+///   `count`, `value`, `percentile`, `latency`, `units`, `adev`
+/// * `exclude=adev` - drop meters whose name contains the given string
+///
+/// each parameter may be repeated, in which case a meter matching any of the
+/// given values is included (or excluded)
+///
+/// the latency heatmaps of metrics with a `LatencyTrace`, `LatencyWaterfall`
+/// or `LatencySvg` interest are rendered on demand at:
+///
+/// * `/waterfall/{metric}.png` - a PNG waterfall of the current heatmap
+/// * `/heatmap/{metric}.svg` - an SVG of the current heatmap, with tooltips
+/// * `/heatmap/{metric}.html` - the SVG in an HTML page, for live viewing
+/// * `/trace/{metric}` - a trace file of the current heatmap
+///
+/// the metrics endpoints also include the `Window` of the `Meters` as
+/// `tic_window_*`, `tic_samples_total`, `tic_batches_total`,
+/// `tic_labels_dropped` and `tic_auto_rejected`, and the meters of the
+/// `Receiver` itself, eg: `tic_artifact_errors`
+///
+/// the meters of each set of `Labels` are rendered with Prometheus labels at
+/// `/metrics` and `/vars`, eg: `ok_count{status="500"} 3`, and in the JSON
+/// output are nested under `labelled` as an array of the labels and value of
+/// each set, keyed by the name of the meter
+///
+/// the `Meters` are also available in InfluxDB line protocol at `/influx`
+///
+/// the raw buckets of the lifetime latency histograms are served at
+/// `/buckets` in the format of `Buckets::serialize`, for merging with those of
+/// other processes. they are kept only for metrics with a
+/// `LatencyPercentileCumulative` interest, and are never cleared
+///
+/// the `Meters` of recent windows, as kept by `Config::history`, are served as
+/// a JSON array at `/history`, oldest first. the `windows=10` parameter limits
+/// the number of windows returned, the filter parameters also apply
 pub struct HttpReporter<T> {
     server: Server,
     controller: Controller<T>,
@@ -34,42 +77,547 @@ impl<T: Eq + Hash + Send + Clone + Display> HttpReporter<T> {
     /// you should run this via `thread::spawn`
     pub fn run(&mut self) {
         for request in self.server.incoming_requests() {
-            let response = match self.controller.get_meters() {
-                Ok(meters) => {
-                    let mut output = "".to_owned();
-                    match request.url() {
-                        "/vars" | "/metrics" => {
-                            for (stat, value) in &meters.data {
-                                output = output + &format!("{} {}\n", stat, value);
-                            }
-                            for (stat, value) in &meters.data_float {
-                                output = output + &format!("{} {}\n", stat, value);
-                            }
+            let (path, query) = split_url(request.url());
+            let response = if path.starts_with("/waterfall/") && path.ends_with(".png") {
+                let metric = decode(&path["/waterfall/".len()..(path.len() - ".png".len())]);
+                match self.controller.get_waterfall(&metric) {
+                    Ok(png) => {
+                        Response::from_data(png).with_header(content_type("image/png"))
+                    }
+                    Err(e) => error_response(&e),
+                }
+            } else if path.starts_with("/heatmap/") &&
+                       (path.ends_with(".svg") || path.ends_with(".html"))
+            {
+                let html = path.ends_with(".html");
+                let extension = if html { ".html" } else { ".svg" };
+                let metric = decode(&path["/heatmap/".len()..(path.len() - extension.len())]);
+                match self.controller.get_svg(&metric, html) {
+                    Ok(svg) => {
+                        let kind = if html {
+                            "text/html; charset=utf-8"
+                        } else {
+                            "image/svg+xml"
+                        };
+                        Response::from_data(svg.into_bytes()).with_header(content_type(kind))
+                    }
+                    Err(e) => error_response(&e),
+                }
+            } else if let Some(metric) = path.strip_prefix("/trace/") {
+                let metric = decode(metric);
+                match self.controller.get_trace(&metric) {
+                    Ok(trace) => {
+                        Response::from_data(trace).with_header(content_type("text/plain"))
+                    }
+                    Err(e) => error_response(&e),
+                }
+            } else if path == "/influx" {
+                match self.controller.get_meters() {
+                    Ok(meters) => {
+                        // the window end is zero until the first window closes
+                        let timestamp = match meters.window().end {
+                            0 => None,
+                            end => Some(end),
+                        };
+                        Response::from_string(influx::render(&meters, timestamp))
+                    }
+                    Err(e) => error_response(&e),
+                }
+            } else if path == "/buckets" {
+                match self.controller.get_buckets() {
+                    Ok(buckets) => {
+                        Response::from_data(buckets.serialize())
+                            .with_header(content_type("application/octet-stream"))
+                    }
+                    Err(e) => error_response(&e),
+                }
+            } else if path == "/history" {
+                self.history(query)
+            } else {
+                self.meters(path, query)
+            };
+
+            let _ = request.respond(response);
+        }
+    }
+
+    // render the `Meters` for the metrics endpoints
+    fn meters(&self, path: &str, query: &str) -> Response<Cursor<Vec<u8>>> {
+        match self.controller.get_meters() {
+            Ok(meters) => {
+                let filter = Filter::parse(query);
+                let readings = readings(&meters, &filter);
+                let labelled = labelled_readings(&meters, &filter);
+                let output = match path {
+                    "/vars" | "/metrics" => {
+                        let mut output = "".to_owned();
+                        for (stat, value) in readings {
+                            output = output + &format!("{} {}\n", stat, value);
                         }
-                        _ => {
-                            output += "{";
-                            for (stat, value) in &meters.data {
-                                output = output + &format!("\"{}\":{},", stat, value);
-                            }
-                            for (stat, value) in &meters.data_float {
-                                output = output + &format!("\"{}\":{},", stat, value);
-                            }
-                            if output.len() > 1 {
-                                output.pop();
-                            }
-                            output += "}";
+                        for (stat, labels, value) in labelled {
+                            output = output + &format!("{}{{{}}} {}\n", stat, labels, value);
                         }
+                        output
                     }
+                    _ => json(readings, labelled),
+                };
 
-                    Response::from_string(output)
+                Response::from_string(output)
+            }
+            Err(_) => {
+                let response = Response::from_string("failed to read meters from receiver");
+                response.with_status_code(500)
+            }
+        }
+    }
+
+    // render the `Meters` of recent windows as a JSON array, oldest first
+    fn history(&self, query: &str) -> Response<Cursor<Vec<u8>>> {
+        let windows = match param(query, "windows") {
+            Some(windows) => {
+                match windows.parse() {
+                    Ok(windows) => windows,
+                    Err(_) => {
+                        let response = Response::from_string("invalid windows parameter");
+                        return response.with_status_code(400);
+                    }
                 }
-                Err(_) => {
-                    let response = Response::from_string("failed to read meters from receiver");
-                    response.with_status_code(500)
+            }
+            None => usize::MAX,
+        };
+        match self.controller.get_history(windows) {
+            Ok(history) => {
+                let filter = Filter::parse(query);
+                let windows: Vec<String> = history
+                    .iter()
+                    .map(|meters| {
+                        json(readings(meters, &filter), labelled_readings(meters, &filter))
+                    })
+                    .collect();
+                Response::from_string(format!("[{}]", windows.join(",")))
+                    .with_header(content_type("application/json"))
+            }
+            Err(e) => error_response(&e),
+        }
+    }
+}
+
+// the `Window` metadata and meters which pass the filter, as named readings
+fn readings<T>(meters: &Meters<T>, filter: &Filter) -> Vec<(String, Reading)>
+where
+    T: Eq + Hash + Send + Clone + Display,
+{
+    let mut readings = Vec::new();
+    let window = meters.window();
+    let metadata = vec![
+        ("tic_window_index", window.index as u64),
+        ("tic_window_start_nanoseconds", window.start),
+        ("tic_window_end_nanoseconds", window.end),
+        ("tic_window_start_counter", window.start_counter),
+        ("tic_window_end_counter", window.end_counter),
+        ("tic_window_duration_nanoseconds", window.duration),
+        ("tic_samples_total", window.samples),
+        ("tic_batches_total", window.batches),
+        ("tic_labels_dropped", window.labels_dropped),
+        ("tic_auto_rejected", window.auto_rejected),
+    ];
+    for (name, value) in metadata {
+        if filter.matches_name(name) {
+            readings.push((name.to_owned(), Reading::Integer(value)));
+        }
+    }
+    for (name, value) in meters.receiver_meters() {
+        if filter.matches_name(name) {
+            readings.push((name.clone(), Reading::Integer(*value)));
+        }
+    }
+    for meter in meters.iter() {
+        let name = meter.name();
+        if filter.matches(&meter.channel.to_string(), meter.kind, &name) {
+            readings.push((name, meter.reading));
+        }
+    }
+    readings
+}
+
+// the labelled meters which pass the filter, as named readings with their
+// labels, in order of name and labels
+fn labelled_readings<T>(meters: &Meters<T>, filter: &Filter) -> Vec<(String, Labels, Reading)>
+where
+    T: Eq + Hash + Send + Clone + Display,
+{
+    let mut readings: Vec<(String, Labels, Reading)> = meters
+        .labelled()
+        .filter_map(|meter| {
+            let name = meter.name();
+            if filter.matches(&meter.channel.to_string(), meter.kind, &name) {
+                Some((name, meter.labels.clone(), meter.reading))
+            } else {
+                None
+            }
+        })
+        .collect();
+    readings.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    readings
+}
+
+// render the named readings as a flat JSON object. labelled readings are
+// nested under `labelled`, as an array of labels and values for each name
+fn json(readings: Vec<(String, Reading)>, labelled: Vec<(String, Labels, Reading)>) -> String {
+    let mut fields: Vec<String> = readings
+        .iter()
+        .map(|(stat, value)| format!("\"{}\":{}", stat, value))
+        .collect();
+    if !labelled.is_empty() {
+        let mut names: Vec<String> = Vec::new();
+        let mut i = 0;
+        while i < labelled.len() {
+            let name = &labelled[i].0;
+            let mut series = Vec::new();
+            while i < labelled.len() && labelled[i].0 == *name {
+                let labels: Vec<String> = labelled[i]
+                    .1
+                    .iter()
+                    .map(|(key, value)| {
+                        format!(
+                            "\"{}\":\"{}\"",
+                            data::escape_label(key),
+                            data::escape_label(value)
+                        )
+                    })
+                    .collect();
+                series.push(format!(
+                    "{{\"labels\":{{{}}},\"value\":{}}}",
+                    labels.join(","),
+                    labelled[i].2
+                ));
+                i += 1;
+            }
+            names.push(format!("\"{}\":[{}]", name, series.join(",")));
+        }
+        fields.push(format!("\"labelled\":{{{}}}", names.join(",")));
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+// build a Content-Type header with the given value
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).unwrap()
+}
+
+// map an error from the `Controller` to a response
+fn error_response(e: &io::Error) -> Response<Cursor<Vec<u8>>> {
+    let code = match e.kind() {
+        io::ErrorKind::NotFound => 404,
+        _ => 500,
+    };
+    Response::from_string(format!("{}", e)).with_status_code(code)
+}
+
+// split a request url into the path and the (possibly empty) query string
+fn split_url(url: &str) -> (&str, &str) {
+    match url.find('?') {
+        Some(i) => (&url[..i], &url[(i + 1)..]),
+        None => (url, ""),
+    }
+}
+
+// the decoded value of the first occurrence of the given query parameter
+fn param(query: &str, key: &str) -> Option<String> {
+    for pair in query.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        if decode(parts.next().unwrap_or("")) == key {
+            return Some(decode(parts.next().unwrap_or("")));
+        }
+    }
+    None
+}
+
+// decode a single query string component, handling '+' and %XX escapes
+fn decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => output.push(b' '),
+            b'%' if i + 2 < bytes.len() && (bytes[i + 1] as char).is_ascii_hexdigit() &&
+                (bytes[i + 2] as char).is_ascii_hexdigit() => {
+                let hex = ((bytes[i + 1] as char).to_digit(16).unwrap() << 4) |
+                    (bytes[i + 2] as char).to_digit(16).unwrap();
+                output.push(hex as u8);
+                i += 2;
+            }
+            b => output.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+// the name of the type of a meter, as used by the `type` query parameter
+fn kind(kind: &MeterKind) -> &'static str {
+    match *kind {
+        MeterKind::Count => "count",
+        MeterKind::Value => "value",
+        MeterKind::LatencyPercentile(_) |
+        MeterKind::LatencyCumulative(_) |
+        MeterKind::LatencyRollup(_, _) => "latency",
+        MeterKind::ValuePercentile(_) |
+        MeterKind::ValueRollup(_, _) => "units",
+        MeterKind::Adev(_) => "adev",
+    }
+}
+
+/// a `Filter` restricts which meters are rendered, built from a query string
+#[derive(Default)]
+struct Filter {
+    prefix: Vec<String>,
+    metric: Vec<String>,
+    kind: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Filter {
+    // build a `Filter` from the query string of a request
+    fn parse(query: &str) -> Filter {
+        let mut filter = Filter::default();
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let key = decode(parts.next().unwrap_or(""));
+            let value = decode(parts.next().unwrap_or(""));
+            if value.is_empty() {
+                continue;
+            }
+            match key.as_str() {
+                "prefix" => filter.prefix.push(value),
+                "metric" => filter.metric.push(value),
+                "type" => filter.kind.push(value),
+                "exclude" => filter.exclude.push(value),
+                "windows" => {} // used by the history endpoint
+                _ => {
+                    debug!("ignoring unknown query parameter: {}", key);
                 }
-            };
+            }
+        }
+        filter
+    }
 
-            let _ = request.respond(response);
+    // returns true if the meter should be rendered
+    fn matches(&self, metric: &str, kind: &MeterKind, name: &str) -> bool {
+        if !self.prefix.is_empty() && !self.prefix.iter().any(|p| name.starts_with(p.as_str())) {
+            return false;
+        }
+        if self.exclude.iter().any(|e| name.contains(e.as_str())) {
+            return false;
+        }
+        if !self.metric.is_empty() && !self.metric.iter().any(|m| m == metric) {
+            return false;
         }
+        if !self.kind.is_empty() {
+            let k = self::kind(kind);
+            if !self.kind.iter().any(|t| {
+                t == k || (t == "percentile" && (k == "latency" || k == "units"))
+            })
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    // returns true if the named reading, which does not belong to a metric,
+    // should be rendered
+    fn matches_name(&self, name: &str) -> bool {
+        if !self.metric.is_empty() || !self.kind.is_empty() {
+            return false;
+        }
+        if !self.prefix.is_empty() && !self.prefix.iter().any(|p| name.starts_with(p.as_str())) {
+            return false;
+        }
+        !self.exclude.iter().any(|e| name.contains(e.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buckets::Buckets;
+    use common::Interest;
+    use render::WaterfallOptions;
+    use std::env;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    // run a receiver with a latency heatmap for `ok`, and serve it over HTTP
+    fn serve() -> SocketAddr {
+        let config = Receiver::configure().poll_delay(Some(Duration::from_millis(10)));
+        let mut receiver = Receiver::configured(config);
+        let file = env::temp_dir().join("tic_http_ok.png");
+        receiver.add_interest(Interest::LatencyWaterfall(
+            "ok".to_owned(),
+            file.to_string_lossy().into_owned(),
+            WaterfallOptions::default(),
+        ));
+        receiver.add_interest(Interest::LatencyPercentileCumulative("ok".to_owned()));
+        receiver.add_interest(Interest::LatencyPercentile("error".to_owned()));
+        let mut reporter = HttpReporter::new(&receiver, "127.0.0.1:0");
+        let address = reporter.server.server_addr();
+        thread::spawn(move || receiver.run());
+        thread::spawn(move || reporter.run());
+        address
+    }
+
+    // the status code and body of the response to a GET of the path
+    fn get(address: SocketAddr, path: &str) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\nConnection: close\r\n\r\n", path).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..end]).into_owned();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, response[(end + 4)..].to_vec())
+    }
+
+    #[test]
+    fn waterfall_route() {
+        let address = serve();
+        let (status, body) = get(address, "/waterfall/ok.png");
+        assert_eq!(status, 200);
+        assert!(body.starts_with(b"\x89PNG\r\n"));
+        assert_eq!(get(address, "/waterfall/missing.png").0, 404);
+    }
+
+    #[test]
+    fn trace_route() {
+        let address = serve();
+        let (status, body) = get(address, "/trace/ok");
+        assert_eq!(status, 200);
+        assert!(!body.is_empty());
+        assert_eq!(get(address, "/trace/missing").0, 404);
+    }
+
+    #[test]
+    fn buckets_route() {
+        let address = serve();
+        let (status, body) = get(address, "/buckets");
+        assert_eq!(status, 200);
+        // only the cumulative interest keeps a lifetime histogram
+        let buckets = Buckets::deserialize(&body).unwrap();
+        assert_eq!(buckets.metrics(), vec!["ok"]);
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_url("/vars"), ("/vars", ""));
+        assert_eq!(split_url("/vars?prefix=ok_"), ("/vars", "prefix=ok_"));
+        assert_eq!(split_url("/?"), ("/", ""));
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(param("windows=10&prefix=ok_", "windows"), Some("10".to_owned()));
+        assert_eq!(param("prefix=ok_", "windows"), None);
+        assert_eq!(param("", "windows"), None);
+    }
+
+    #[test]
+    fn labelled_json() {
+        let readings = vec![("ok_count".to_owned(), Reading::Integer(3))];
+        let labelled = vec![
+            (
+                "ok_count".to_owned(),
+                Labels::new().with("status", "200"),
+                Reading::Integer(2),
+            ),
+            (
+                "ok_count".to_owned(),
+                Labels::new().with("status", "500"),
+                Reading::Integer(1),
+            ),
+        ];
+        assert_eq!(json(Vec::new(), Vec::new()), "{}");
+        assert_eq!(
+            json(readings, labelled),
+            "{\"ok_count\":3,\"labelled\":{\"ok_count\":[\
+             {\"labels\":{\"status\":\"200\"},\"value\":2},\
+             {\"labels\":{\"status\":\"500\"},\"value\":1}]}}"
+        );
+    }
+
+    #[test]
+    fn decoding() {
+        assert_eq!(decode("ok_"), "ok_");
+        assert_eq!(decode("a+b"), "a b");
+        assert_eq!(decode("ok%5F"), "ok_");
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz"), "%zz");
+    }
+
+    // check a filter against the meter of the given kind for a metric
+    fn matches(filter: &Filter, metric: &str, kind: MeterKind) -> bool {
+        let name = format!("{}_{}", metric, kind);
+        filter.matches(metric, &kind, &name)
+    }
+
+    fn p50_latency() -> MeterKind {
+        MeterKind::LatencyPercentile("p50".to_owned())
+    }
+
+    #[test]
+    fn filter_empty() {
+        let filter = Filter::parse("");
+        assert!(matches(&filter, "ok", MeterKind::Count));
+        assert!(matches(&filter, "ok", MeterKind::Adev(1)));
+    }
+
+    #[test]
+    fn filter_prefix() {
+        let filter = Filter::parse("prefix=ok_");
+        assert!(matches(&filter, "ok", MeterKind::Count));
+        assert!(!matches(&filter, "total", MeterKind::Count));
+    }
+
+    #[test]
+    fn filter_metric() {
+        let filter = Filter::parse("metric=ok");
+        assert!(matches(&filter, "ok", MeterKind::Count));
+        assert!(matches(&filter, "ok", p50_latency()));
+        assert!(!matches(&filter, "okay", MeterKind::Count));
+        assert!(!matches(&filter, "ok_p50", MeterKind::Count));
+    }
+
+    #[test]
+    fn filter_type() {
+        let filter = Filter::parse("type=percentile");
+        assert!(matches(&filter, "ok", p50_latency()));
+        assert!(matches(&filter, "ok", MeterKind::ValuePercentile("p50".to_owned())));
+        assert!(!matches(&filter, "ok", MeterKind::Count));
+        assert!(!matches(&filter, "ok", MeterKind::Adev(1)));
+
+        let filter = Filter::parse("type=count&type=adev");
+        assert!(matches(&filter, "ok", MeterKind::Count));
+        assert!(matches(&filter, "ok", MeterKind::Adev(1)));
+        assert!(!matches(&filter, "ok", MeterKind::Value));
+    }
+
+    #[test]
+    fn filter_names() {
+        assert!(Filter::parse("").matches_name("tic_samples_total"));
+        assert!(Filter::parse("prefix=tic_").matches_name("tic_samples_total"));
+        assert!(!Filter::parse("prefix=ok_").matches_name("tic_samples_total"));
+        assert!(!Filter::parse("metric=ok").matches_name("tic_samples_total"));
+        assert!(!Filter::parse("exclude=tic_").matches_name("tic_samples_total"));
+    }
+
+    #[test]
+    fn filter_exclude() {
+        let filter = Filter::parse("metric=ok&exclude=adev");
+        assert!(matches(&filter, "ok", MeterKind::Count));
+        assert!(!matches(&filter, "ok", MeterKind::Adev(1)));
+        assert!(!matches(&filter, "total", MeterKind::Count));
     }
 }
@@ -0,0 +1,426 @@
+// `Influx` renders `Meters` in the InfluxDB line protocol
+
+use data::{Meters, Reading};
+use sink::{Sink, Window};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// render the `Meters` in the InfluxDB line protocol
+///
+/// each metric becomes a measurement named by its label, with fields for the
+/// count, value, each percentile and each ADEV tau. each meter of the
+/// `Receiver` itself becomes a measurement with a single `value` field. the
+/// timestamp is given in nanoseconds since the unix epoch, if it is `None`
+/// the server will assign the time of receipt
+///
+/// the line protocol has no NaN or infinite floats, so those fields are left
+/// out, along with any measurement which is left without fields
+pub fn render<T>(meters: &Meters<T>, timestamp: Option<u64>) -> String
+where
+    T: Hash + Eq + Send + Display + Clone,
+{
+    let mut measurements: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for meter in meters.iter() {
+        let value = match meter.reading {
+            Reading::Integer(v) => format!("{}i", v),
+            Reading::Float(v) if v.is_finite() => format!("{}", v),
+            Reading::Float(_) => continue,
+        };
+        measurements
+            .entry(meter.channel.to_string())
+            .or_default()
+            .push((meter.kind.to_string(), value));
+    }
+    for (name, value) in meters.receiver_meters() {
+        measurements
+            .entry(name.clone())
+            .or_default()
+            .push(("value".to_owned(), format!("{}i", value)));
+    }
+
+    let mut output = String::new();
+    for (metric, mut fields) in measurements {
+        fields.sort();
+        output.push_str(&escape(&metric, &[',', ' ']));
+        for (i, (field, value)) in fields.iter().enumerate() {
+            output.push(if i == 0 { ' ' } else { ',' });
+            output.push_str(&escape(field, &[',', '=', ' ']));
+            output.push('=');
+            output.push_str(value);
+        }
+        if let Some(timestamp) = timestamp {
+            output.push_str(&format!(" {}", timestamp));
+        }
+        output.push('\n');
+    }
+    output
+}
+
+// escape the given characters with a backslash
+fn escape(input: &str, special: &[char]) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        if special.contains(&c) {
+            output.push('\\');
+        }
+        output.push(c);
+    }
+    output
+}
+
+#[derive(Clone)]
+enum Target {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+    File(PathBuf),
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    File(File),
+}
+
+/// an `Influx` exporter is a `Sink` which writes the `Meters` in the InfluxDB
+/// line protocol at the end of each window, timestamped with the end time of
+/// the window
+///
+/// if a write fails, the data for the window is dropped and the connection is
+/// re-established after an exponentially increasing backoff
+pub struct Influx {
+    target: Target,
+    max_packet_size: usize,
+    timeout: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+    connection: Option<Connection>,
+}
+
+impl Clone for Influx {
+    /// clones share the configuration, but not the connection
+    fn clone(&self) -> Influx {
+        Influx {
+            target: self.target.clone(),
+            max_packet_size: self.max_packet_size,
+            timeout: self.timeout,
+            min_backoff: self.min_backoff,
+            max_backoff: self.max_backoff,
+            backoff: self.min_backoff,
+            retry_at: None,
+            connection: None,
+        }
+    }
+}
+
+impl Influx {
+    fn new(target: Target) -> Influx {
+        Influx {
+            target: target,
+            max_packet_size: 1432,
+            timeout: Duration::from_secs(1),
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            backoff: Duration::from_secs(1),
+            retry_at: None,
+            connection: None,
+        }
+    }
+
+    /// create an `Influx` exporter which sends UDP datagrams to the address
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Influx;
+    /// let influx = Influx::udp("127.0.0.1:8089");
+    /// ```
+    pub fn udp<U: ToSocketAddrs>(address: U) -> Influx {
+        Influx::new(Target::Udp(resolve(address)))
+    }
+
+    /// create an `Influx` exporter which writes to a TCP connection
+    pub fn tcp<U: ToSocketAddrs>(address: U) -> Influx {
+        Influx::new(Target::Tcp(resolve(address)))
+    }
+
+    /// create an `Influx` exporter which writes to a unix stream socket
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<Path>>(path: P) -> Influx {
+        Influx::new(Target::Unix(path.as_ref().to_path_buf()))
+    }
+
+    /// create an `Influx` exporter which appends to a file
+    pub fn file<P: AsRef<Path>>(path: P) -> Influx {
+        Influx::new(Target::File(path.as_ref().to_path_buf()))
+    }
+
+    /// set the max size of a single UDP datagram in bytes: default 1432
+    pub fn max_packet_size(mut self, bytes: usize) -> Self {
+        self.max_packet_size = bytes;
+        self
+    }
+
+    /// set the connect and write timeout of TCP and unix socket connections:
+    /// default 1s
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// set the range of the reconnect backoff: default 1s to 60s
+    ///
+    /// the backoff doubles after each failed attempt and resets once a
+    /// window has been written successfully
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = cmp::max(min, max);
+        self.backoff = min;
+        self
+    }
+
+    /// write the `Meters` with the given timestamp, in nanoseconds since the
+    /// unix epoch
+    pub fn emit<T>(&mut self, meters: &Meters<T>, timestamp: u64) -> Result<(), io::Error>
+    where
+        T: Hash + Eq + Send + Display + Clone,
+    {
+        if let Some(retry_at) = self.retry_at {
+            if Instant::now() < retry_at {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "waiting to reconnect to influx",
+                ));
+            }
+        }
+
+        let output = render(meters, Some(timestamp));
+        match self.write(&output) {
+            Ok(()) => {
+                self.backoff = self.min_backoff;
+                self.retry_at = None;
+                Ok(())
+            }
+            Err(e) => {
+                self.connection = None;
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = cmp::min(self.backoff * 2, self.max_backoff);
+                Err(e)
+            }
+        }
+    }
+
+    // write the rendered lines, connecting first if required
+    fn write(&mut self, output: &str) -> Result<(), io::Error> {
+        if self.connection.is_none() {
+            self.connection = Some(self.connect()?);
+        }
+        match *self.connection.as_mut().unwrap() {
+            Connection::Udp(ref socket) => {
+                let address = match self.target {
+                    Target::Udp(address) => address,
+                    _ => unreachable!(),
+                };
+                let mut packet = String::new();
+                for line in output.lines() {
+                    if !packet.is_empty() && packet.len() + line.len() + 1 > self.max_packet_size {
+                        socket.send_to(packet.as_bytes(), address)?;
+                        packet.clear();
+                    }
+                    packet.push_str(line);
+                    packet.push('\n');
+                }
+                if !packet.is_empty() {
+                    socket.send_to(packet.as_bytes(), address)?;
+                }
+                Ok(())
+            }
+            Connection::Tcp(ref mut stream) => stream.write_all(output.as_bytes()),
+            #[cfg(unix)]
+            Connection::Unix(ref mut stream) => stream.write_all(output.as_bytes()),
+            Connection::File(ref mut file) => {
+                file.write_all(output.as_bytes())?;
+                file.flush()
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<Connection, io::Error> {
+        match self.target {
+            Target::Udp(address) => {
+                let local = if address.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                Ok(Connection::Udp(UdpSocket::bind(local)?))
+            }
+            Target::Tcp(address) => {
+                let stream = TcpStream::connect_timeout(&address, self.timeout)?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                Ok(Connection::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Target::Unix(ref path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                Ok(Connection::Unix(stream))
+            }
+            Target::File(ref path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Ok(Connection::File(file))
+            }
+        }
+    }
+}
+
+impl<T: Hash + Eq + Send + Display + Clone> Sink<T> for Influx {
+    fn window(&mut self, window: &Window, meters: &Meters<T>) -> Result<(), io::Error> {
+        match self.emit(meters, window.end) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                debug!("skipped influx: {}", e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+fn resolve<U: ToSocketAddrs>(address: U) -> SocketAddr {
+    address
+        .to_socket_addrs()
+        .expect("SocketAddr lookup failed")
+        .next()
+        .expect("SocketAddr resolved to empty set")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Percentile;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn render_lines() {
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 10);
+        meters.set_latency_percentile("ok".to_owned(), Percentile("p50".to_owned(), 50.0), 100);
+        meters.set_adev("ok".to_owned(), 1, 0.5);
+        meters.set_value("total".to_owned(), 3);
+
+        assert_eq!(
+            render(&meters, Some(1500000000000000000)),
+            "ok count=10i,p50_nanoseconds=100i,tau_1_adev=0.5 1500000000000000000\n\
+             total value=3i 1500000000000000000\n"
+        );
+        assert_eq!(render(&meters, None).lines().nth(1), Some("total value=3i"));
+    }
+
+    #[test]
+    fn render_receiver_meters() {
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 1);
+        meters.set_receiver_meter("tic_artifact_errors", 2);
+        assert_eq!(
+            render(&meters, None),
+            "ok count=1i\ntic_artifact_errors value=2i\n"
+        );
+    }
+
+    #[test]
+    fn render_escaped() {
+        let mut meters = Meters::<String>::new();
+        meters.set_count("a b,c".to_owned(), 1);
+        assert_eq!(render(&meters, None), "a\\ b\\,c count=1i\n");
+    }
+
+    #[test]
+    fn render_non_finite() {
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 1);
+        meters.set_adev("ok".to_owned(), 1, f64::NAN);
+        meters.set_adev("ok".to_owned(), 2, 0.5);
+        meters.set_adev("idle".to_owned(), 1, f64::INFINITY);
+        meters.set_adev("idle".to_owned(), 2, f64::NEG_INFINITY);
+        assert_eq!(render(&meters, None), "ok count=1i,tau_2_adev=0.5\n");
+    }
+
+    #[test]
+    fn emit_udp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut influx = Influx::udp(socket.local_addr().unwrap());
+
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 10);
+        influx.emit(&meters, 42).unwrap();
+
+        let mut buffer = [0; 1500];
+        let (len, _) = socket.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"ok count=10i 42\n");
+    }
+
+    #[test]
+    fn emit_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut influx = Influx::tcp(listener.local_addr().unwrap())
+            .timeout(Duration::from_millis(500));
+
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 10);
+        influx.emit(&meters, 42).unwrap();
+        drop(influx);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "ok count=10i 42\n");
+    }
+
+    #[test]
+    fn backoff_after_failure() {
+        // bind and drop a listener to find a port which refuses connections
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut influx = Influx::tcp(address).backoff(
+            Duration::from_secs(60),
+            Duration::from_secs(120),
+        );
+
+        let meters = Meters::<String>::new();
+        assert!(influx.emit(&meters, 0).is_err());
+        assert_eq!(
+            influx.emit(&meters, 0).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(influx.backoff, Duration::from_secs(120));
+        // the window is skipped rather than reported as failed
+        assert!(Sink::window(&mut influx, &Window::default(), &meters).is_ok());
+
+        // clones start without a backoff
+        let mut clone = influx.clone();
+        assert_eq!(clone.backoff, Duration::from_secs(60));
+        assert_ne!(
+            clone.emit(&meters, 0).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+}
@@ -4,9 +4,10 @@
 //!
 //! * high throughput - millions of samples per second
 //! * latched histogram - for analyzing the full distribution of sample lengths
-//! * heatmaps - to generate distribution traces and waterfalls
+//! * heatmaps - to generate distribution traces, waterfalls and interactive SVGs
 //! * meters - to expose readings for client usage
 //! * http metrics - simple metrics on http for scraping and monitoring, Prometheus compatible
+//! * sinks - push meters to StatsD, Graphite, InfluxDB, HdrHistogram logs or your own `Sink`
 //! * generic - channel type is generic, and used to label the type of sample
 //! * flexible - per channel stats are accessible by registering appropriate `Interest`s
 //!
@@ -72,14 +73,14 @@
 //! // put your application logic here, and increment stats
 //! for _ in 0..100 {
 //!     let start = clocksource.counter();
-//! 	// do some work that takes some time
+//!     // do some work that takes some time
 //!     let stop = clocksource.counter();
-//!		sender.send(Sample::new(start, stop, Metric::Ok));
+//!     sender.send(Sample::new(start, stop, Metric::Ok));
 //! }
 //!
 //! // stats will be available on the http_listen port while main() is running
-#![cfg_attr(feature = "cargo-clippy", deny(missing_docs))]
-#![cfg_attr(feature = "cargo-clippy", deny(warnings))]
+#![cfg_attr(clippy, deny(missing_docs))]
+#![cfg_attr(clippy, deny(warnings))]
 #![cfg_attr(feature = "benchmark", feature(test))]
 #[cfg(feature = "benchmark")]
 extern crate test;
@@ -94,22 +95,42 @@ extern crate log;
 extern crate mio;
 extern crate mio_extras;
 extern crate mpmc;
+extern crate png;
+extern crate time;
 extern crate tiny_http;
-extern crate waterfall;
 
+mod artifacts;
+mod buckets;
 mod common;
 mod config;
 mod data;
 mod receiver;
+mod render;
 mod sender;
+mod sink;
 mod controller;
+mod graphite;
+mod hdr;
 mod http;
+mod influx;
+mod statsd;
+mod trace;
 
+pub use artifacts::ArtifactError;
+pub use buckets::Buckets;
 pub use clocksource::Clocksource;
-pub use common::{Interest, Percentile, SECOND};
+pub use common::{Interest, Percentile, Retention, SECOND};
 pub use config::Config;
 pub use controller::Controller;
-pub use data::{Meters, Sample};
+pub use data::{LabelledIter as LabelledMetersIter, LabelledMeter, Labels, Meter, MeterKind, Meters,
+               MetersIter, Reading, Sample};
+pub use graphite::{Graphite, Protocol as GraphiteProtocol};
+pub use hdr::HdrLog;
 pub use http::HttpReporter;
+pub use influx::Influx;
 pub use receiver::Receiver;
+pub use render::{Palette, Scale, WaterfallOptions};
 pub use sender::Sender;
+pub use sink::{Run, Sink, SinkFactory, Window, WindowHistograms};
+pub use statsd::Statsd;
+pub use trace::Trace;
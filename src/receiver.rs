@@ -1,17 +1,26 @@
 #![allow(deprecated)]
 
+use artifacts::{self, ArtifactError, Artifacts, Placeholders};
+use buckets::Buckets;
 use clocksource::Clocksource;
-use common::{self, ControlMessage, Interest, Percentile};
+use common::{self, ControlMessage, Interest, Percentile, Retention};
 use config::Config;
 use controller::Controller;
-use data::{Allans, Counters, Gauges, Heatmaps, Histograms, Meters, Sample};
+use data::{self, Allans, Counters, Gauges, Heatmaps, Histograms, Labels, MeterKind, Meters,
+           Reading, Rollups, Sample};
+use fnv::FnvHashMap;
 use mio::{self, Events, Poll, PollOpt, Ready};
 use mio_extras::channel;
 use mpmc::Queue;
+use render::{Unit, WaterfallOptions};
 use sender::Sender;
-use std::collections::HashSet;
+use sink::{Run, Sink, Window, WindowHistograms};
+use std::cmp;
+use std::collections::{HashSet, VecDeque};
 use std::fmt::Display;
 use std::hash::Hash;
+use std::io;
+use std::mem;
 use std::sync::Arc;
 
 // define token numbers for data and control queues
@@ -25,6 +34,13 @@ enum Token {
 pub struct Receiver<T> {
     window_time: u64,
     window_duration: u64,
+    window_index: usize,
+    run_index: usize,
+    samples: u64,
+    batches: u64,
+    artifact_errors: u64,
+    labels_dropped: u64,
+    auto_rejected: u64,
     end_time: u64,
     run_duration: u64,
     config: Config<T>,
@@ -38,14 +54,25 @@ pub struct Receiver<T> {
     gauges: Gauges<T>,
     latency_histograms: Histograms<T>,
     value_histograms: Histograms<T>,
+    latency_rollups: Rollups<T>,
+    value_rollups: Rollups<T>,
+    cumulative_histograms: Histograms<T>,
+    labelled_counters: Counters<(T, Labels)>,
+    labelled_histograms: Histograms<(T, Labels)>,
+    label_series: FnvHashMap<T, usize>,
+    auto_channels: HashSet<T>,
+    auto_interests: HashSet<Interest<T>>,
     meters: Meters<T>,
+    history: VecDeque<Meters<T>>,
     interests: HashSet<Interest<T>>,
     taus: Vec<usize>,
     percentiles: Vec<Percentile>,
     latency_heatmaps: Heatmaps<T>,
     value_heatmaps: Heatmaps<T>,
+    artifacts: Artifacts,
     clocksource: Clocksource,
     poll: Poll,
+    sinks: Vec<Box<dyn Sink<T>>>,
 }
 
 impl<T: Hash + Eq + Send + Clone + Display> Default for Receiver<T> {
@@ -62,7 +89,14 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
     }
 
     /// create a `Receiver` from a tic::Config
-    pub fn configured(config: Config<T>) -> Receiver<T> {
+    ///
+    /// if `Config::trace_file` and `Config::waterfall_file` are the same path
+    /// only the trace is saved, use `try_configured` to reject such a config
+    pub fn configured(mut config: Config<T>) -> Receiver<T> {
+        if let Err(e) = check_defaults(&config) {
+            error!("{}, the waterfall is not saved", e);
+            config.waterfall_file = None;
+        }
         let (data_tx, data_rx) = channel::sync_channel::<Vec<Sample<T>>>(config.capacity);
         let (control_tx, control_rx) = channel::sync_channel::<ControlMessage<T>>(config.capacity);
         let empty_queue = Arc::new(Queue::with_capacity(config.capacity));
@@ -81,6 +115,26 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
         let end_time = start_time + run_duration;
 
         let max_tau = config.max_tau;
+        let history = config.history;
+        let rolling = config.rolling_heatmaps;
+        let heatmap_config = config.heatmap_config;
+        let histogram_config = config.histogram_config;
+        let artifacts =
+            Artifacts::new(config.artifact_directory.clone(), config.artifact_retention);
+        let horizons: Vec<(String, usize)> = config
+            .rollups
+            .iter()
+            .map(|&seconds| {
+                let windows = cmp::max(1, seconds / cmp::max(1, config.duration));
+                (data::horizon_label(seconds), windows)
+            })
+            .collect();
+        // each receiver builds sinks of its own
+        let sinks = config
+            .sinks
+            .iter()
+            .map(|factory| (factory.lock().unwrap_or_else(|e| e.into_inner()))())
+            .collect();
 
         let poll = Poll::new().unwrap();
         poll.register(
@@ -99,6 +153,13 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
         Receiver {
             window_duration: window_duration,
             window_time: window_time,
+            window_index: 0,
+            run_index: 0,
+            samples: 0,
+            batches: 0,
+            artifact_errors: 0,
+            labels_dropped: 0,
+            auto_rejected: 0,
             run_duration: run_duration,
             end_time: end_time,
             config: config,
@@ -110,19 +171,44 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
             allans: Allans::new(max_tau),
             counters: Counters::new(),
             gauges: Gauges::new(),
-            latency_histograms: Histograms::new(),
-            value_histograms: Histograms::new(),
+            latency_histograms: Histograms::new().histogram_config(histogram_config),
+            value_histograms: Histograms::new().histogram_config(histogram_config),
+            latency_rollups: Rollups::new(histogram_config, horizons.clone()),
+            value_rollups: Rollups::new(histogram_config, horizons),
+            cumulative_histograms: Histograms::new().histogram_config(histogram_config),
+            labelled_counters: Counters::new(),
+            labelled_histograms: Histograms::new().histogram_config(histogram_config),
+            label_series: FnvHashMap::default(),
+            auto_channels: HashSet::new(),
+            auto_interests: HashSet::new(),
             meters: Meters::new(),
+            history: VecDeque::with_capacity(history),
             interests: HashSet::new(),
             taus: common::default_taus(),
             percentiles: common::default_percentiles(),
-            latency_heatmaps: Heatmaps::new(slices, start_time),
-            value_heatmaps: Heatmaps::new(slices, start_time),
+            latency_heatmaps: Heatmaps::new(slices, start_time)
+                .heatmap_config(heatmap_config)
+                .histogram_config(histogram_config)
+                .rolling(rolling)
+                .unit(Unit::Nanoseconds),
+            value_heatmaps: Heatmaps::new(slices, start_time)
+                .heatmap_config(heatmap_config)
+                .histogram_config(histogram_config)
+                .rolling(rolling),
+            artifacts: artifacts,
             clocksource: clocksource,
             poll: poll,
+            sinks: sinks,
         }
     }
 
+    /// create a `Receiver` from a tic::Config, or an `InvalidInput` error if
+    /// `Config::trace_file` and `Config::waterfall_file` are the same path
+    pub fn try_configured(config: Config<T>) -> Result<Receiver<T>, io::Error> {
+        check_defaults(&config)?;
+        Ok(Receiver::configured(config))
+    }
+
     /// Create a new Config which can be used to build() a Receiver
     pub fn configure() -> Config<T> {
         Config::default()
@@ -149,7 +235,52 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
     }
 
     /// register a stat for export
+    ///
+    /// an interest whose artifact would overwrite another one is ignored, use
+    /// `try_add_interest` to get the error
     pub fn add_interest(&mut self, interest: Interest<T>) {
+        if let Err(e) = self.try_add_interest(interest) {
+            error!("ignoring interest: {}", e);
+        }
+    }
+
+    /// register a stat for export, or an `InvalidInput` error if its artifact
+    /// would be saved to the same path as the default trace or waterfall of
+    /// its metric, or as the artifact of another interest
+    pub fn try_add_interest(&mut self, interest: Interest<T>) -> Result<(), io::Error> {
+        if let Some((kind, key, file)) = artifact(&interest) {
+            let metric = key.to_string();
+            let defaults = [
+                ("Config::trace_file", &self.config.trace_file),
+                ("Config::waterfall_file", &self.config.waterfall_file),
+            ];
+            for &(name, default) in &defaults {
+                if let Some(ref default) = *default {
+                    if artifacts::for_metric(default, &metric) == file {
+                        let first = format!("{} of {}", kind, metric);
+                        return Err(collision(&first, name, file));
+                    }
+                }
+            }
+            for other in &self.interests {
+                if *other == interest {
+                    continue;
+                }
+                if let Some((other_kind, other_key, other_file)) = artifact(other) {
+                    if other_file == file {
+                        let first = format!("{} of {}", kind, metric);
+                        let second = format!("{} of {}", other_kind, other_key);
+                        return Err(collision(&first, &second, file));
+                    }
+                }
+            }
+        }
+        self.init_interest(interest);
+        Ok(())
+    }
+
+    // initialize the stats of an interest and register it
+    fn init_interest(&mut self, interest: Interest<T>) {
         match interest.clone() {
             Interest::AllanDeviation(key) => {
                 self.allans.init(key);
@@ -161,51 +292,233 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
                 self.gauges.init(key);
             }
             Interest::LatencyPercentile(key) => {
+                if self.config.trace_file.is_some() || self.config.waterfall_file.is_some() {
+                    self.init_latency_heatmap(key.clone());
+                }
+                self.latency_rollups.init(key.clone());
                 self.latency_histograms.init(key);
             }
+            Interest::LatencyPercentileCumulative(key) => {
+                if !self.cumulative_histograms.contains(&key) {
+                    self.cumulative_histograms.init(key);
+                }
+            }
             Interest::ValuePercentile(key) => {
+                self.value_rollups.init(key.clone());
                 self.value_histograms.init(key);
             }
             Interest::LatencyTrace(key, _) |
-            Interest::LatencyWaterfall(key, _) => {
-                self.latency_heatmaps.init(key);
+            Interest::LatencyWaterfall(key, _, _) |
+            Interest::LatencySvg(key, _) |
+            Interest::LatencySeries(key, _) => {
+                self.init_latency_heatmap(key);
             }
             Interest::ValueTrace(key, _) |
-            Interest::ValueWaterfall(key, _) => {
+            Interest::ValueWaterfall(key, _, _) => {
                 self.value_heatmaps.init(key);
             }
+            Interest::AllCount | Interest::AllLatencyPercentile => {
+                self.interests.insert(interest);
+                for key in self.auto_channels.clone() {
+                    self.register(key);
+                }
+                return;
+            }
         }
         self.interests.insert(interest);
     }
 
     /// de-register a stat for export
     pub fn remove_interest(&mut self, interest: &Interest<T>) {
+        self.interests.remove(interest);
+        self.auto_interests.remove(interest);
         match interest.clone() {
             Interest::AllanDeviation(key) => {
                 self.allans.remove(key);
             }
             Interest::Count(key) => {
-                self.counters.remove(key);
+                self.labelled_counters.retain(|series| series.0 != key);
+                self.counters.remove(key.clone());
+                self.release_labels(key);
             }
             Interest::Gauge(key) => {
                 self.gauges.remove(key);
             }
             Interest::LatencyPercentile(key) => {
-                self.latency_histograms.remove(key);
+                self.labelled_histograms.retain(|series| series.0 != key);
+                self.latency_rollups.remove(&key);
+                self.latency_histograms.remove(key.clone());
+                self.release_labels(key.clone());
+                self.release_latency_heatmap(key);
+            }
+            Interest::LatencyPercentileCumulative(key) => {
+                self.cumulative_histograms.remove(key);
             }
             Interest::ValuePercentile(key) => {
+                self.value_rollups.remove(&key);
                 self.value_histograms.remove(key);
             }
             Interest::LatencyTrace(key, _) |
-            Interest::LatencyWaterfall(key, _) => {
-                self.latency_heatmaps.remove(key);
+            Interest::LatencyWaterfall(key, _, _) |
+            Interest::LatencySvg(key, _) |
+            Interest::LatencySeries(key, _) => {
+                self.release_latency_heatmap(key);
             }
             Interest::ValueTrace(key, _) |
-            Interest::ValueWaterfall(key, _) => {
+            Interest::ValueWaterfall(key, _, _) => {
                 self.value_heatmaps.remove(key);
             }
+            Interest::AllCount | Interest::AllLatencyPercentile => {
+                for key in self.auto_channels.clone() {
+                    let registered = match *interest {
+                        Interest::AllCount => Interest::Count(key),
+                        _ => Interest::LatencyPercentile(key),
+                    };
+                    if self.auto_interests.contains(&registered) {
+                        self.remove_interest(&registered);
+                    }
+                }
+                // forget the registered metrics once no wildcard is left,
+                // which frees them from the `Config::auto_channels` limit
+                if !self.wildcard() {
+                    self.auto_channels.clear();
+                }
+            }
+        }
+    }
+
+    // true if a wildcard interest registers the metrics of new samples
+    fn wildcard(&self) -> bool {
+        let (count, latency) = self.wildcards();
+        count || latency
+    }
+
+    // whether the `AllCount` and `AllLatencyPercentile` interests are present
+    fn wildcards(&self) -> (bool, bool) {
+        (
+            self.interests.contains(&Interest::AllCount),
+            self.interests.contains(&Interest::AllLatencyPercentile),
+        )
+    }
+
+    // register the metric of a sample for the given wildcards, unless it is
+    // already recorded by them. once `Config::auto_channels` metrics are
+    // registered, samples which a wildcard would record are rejected.
+    // returns false if the sample was rejected
+    fn auto_register(&mut self, key: &T, wildcards: (bool, bool)) -> bool {
+        // a recorded metric is the common case, and needs no lookup of the
+        // registered metrics
+        let count = wildcards.0 && !self.counters.contains(key);
+        let latency = wildcards.1 && !self.latency_histograms.contains(key);
+        if (!count && !latency) || self.auto_channels.contains(key) {
+            return true;
+        }
+        if self.auto_channels.len() < self.config.auto_channels {
+            self.auto_channels.insert(key.clone());
+            self.register(key.clone());
+            return true;
+        }
+        self.auto_rejected += 1;
+        false
+    }
+
+    // add an interest for the metric for each wildcard interest, leaving any
+    // interests which were added for the metric directly
+    fn register(&mut self, key: T) {
+        let mut interests = Vec::new();
+        if self.interests.contains(&Interest::AllCount) {
+            interests.push(Interest::Count(key.clone()));
+        }
+        if self.interests.contains(&Interest::AllLatencyPercentile) {
+            interests.push(Interest::LatencyPercentile(key));
+        }
+        for interest in interests {
+            if !self.interests.contains(&interest) {
+                self.auto_interests.insert(interest.clone());
+                self.add_interest(interest);
+            }
+        }
+    }
+
+    // start a latency heatmap for the metric, unless it already has one
+    fn init_latency_heatmap(&mut self, key: T) {
+        if !self.latency_heatmaps.data.contains_key(&key) {
+            self.latency_heatmaps.init(key);
+        }
+    }
+
+    // remove the latency heatmap for the metric, unless a remaining interest
+    // still saves it
+    fn release_latency_heatmap(&mut self, key: T) {
+        let defaults = self.config.trace_file.is_some() || self.config.waterfall_file.is_some();
+        let needed = self.interests.iter().any(|interest| match *interest {
+            Interest::LatencyTrace(ref k, _) |
+            Interest::LatencyWaterfall(ref k, _, _) |
+            Interest::LatencySvg(ref k, _) |
+            Interest::LatencySeries(ref k, _) => *k == key,
+            Interest::LatencyPercentile(ref k) => defaults && *k == key,
+            _ => false,
+        });
+        if !needed {
+            self.latency_heatmaps.remove(key);
+        }
+    }
+
+    // recount the label sets of a metric after its counts or latencies are
+    // removed, as a set is shared by both and only frees its slot once
+    // neither holds it. the metric is forgotten once it has no sets left
+    fn release_labels(&mut self, key: T) {
+        let mut sets = HashSet::new();
+        for series in self.labelled_counters.iter().map(|(series, _)| series) {
+            if series.0 == key {
+                sets.insert(&series.1);
+            }
+        }
+        for series in self.labelled_histograms.data.keys() {
+            if series.0 == key {
+                sets.insert(&series.1);
+            }
+        }
+        if sets.is_empty() {
+            self.label_series.remove(&key);
+        } else {
+            let len = sets.len();
+            self.label_series.insert(key, len);
+        }
+    }
+
+    // record a sample in the series of its labels, for the metrics which have
+    // a `Count` or `LatencyPercentile` interest. a new set of labels is
+    // dropped once the metric has `Config::label_cardinality` sets, which are
+    // shared by its counts and latencies
+    fn record_labelled(&mut self, sample: &Sample<T>, duration: u64) {
+        let channel = sample.channel();
+        let counted = self.counters.contains(&channel);
+        let timed = self.latency_histograms.contains(&channel);
+        if !counted && !timed {
+            return;
+        }
+        let key = (channel, sample.labels().clone());
+        if !self.labelled_counters.contains(&key) && !self.labelled_histograms.contains(&key) {
+            let series = self.label_series.entry(key.0.clone()).or_insert(0);
+            if *series >= self.config.label_cardinality {
+                self.labels_dropped += 1;
+                return;
+            }
+            *series += 1;
+        }
+        if counted {
+            if !self.labelled_counters.contains(&key) {
+                self.labelled_counters.init(key.clone());
+            }
+            self.labelled_counters.increment_by(key.clone(), sample.count());
+        }
+        if timed {
+            if !self.labelled_histograms.contains(&key) {
+                self.labelled_histograms.init(key.clone());
+            }
+            self.labelled_histograms.increment(key, duration);
         }
-        self.interests.remove(interest);
     }
 
     /// clear the heatmaps
@@ -214,6 +527,13 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
         self.value_heatmaps.clear();
     }
 
+    // bring rolling heatmaps up to date with the current time
+    fn roll_heatmaps(&mut self) {
+        let now = self.clocksource.convert(self.clocksource.counter()) as u64;
+        self.latency_heatmaps.roll(now);
+        self.value_heatmaps.roll(now);
+    }
+
     /// run the receive loop for one window
     pub fn run_once(&mut self) {
         trace!("run once");
@@ -232,7 +552,21 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
                 let token = event.token().0;
                 if token == Token::Data as usize {
                     if let Ok(mut results) = self.data_rx.try_recv() {
+                        self.batches += 1;
+                        self.samples += results.len() as u64;
+                        // samples of a batch are often of the same metric, so
+                        // the last one registered is not looked up again
+                        let wildcards = self.wildcards();
+                        let mut registered = None;
                         for result in &results {
+                            if wildcards.0 || wildcards.1 {
+                                let metric = result.metric();
+                                if registered.as_ref() != Some(&metric) &&
+                                    self.auto_register(&metric, wildcards)
+                                {
+                                    registered = Some(metric);
+                                }
+                            }
                             let t0 = self.clocksource.convert(result.start());
                             let t1 = self.clocksource.convert(result.stop());
                             let dt = t1 - t0;
@@ -243,6 +577,10 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
                                 result.metric(),
                                 dt as u64,
                             );
+                            self.cumulative_histograms.increment(
+                                result.metric(),
+                                dt as u64,
+                            );
                             self.value_histograms.increment(
                                 result.metric(),
                                 result.count(),
@@ -257,6 +595,9 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
                                 t0 as u64,
                                 result.count(),
                             );
+                            if !result.labels().is_empty() {
+                                self.record_labelled(result, dt as u64);
+                            }
                         }
                         results.clear();
                         let _ = self.empty_queue.push(results);
@@ -275,6 +616,22 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
                                 let meters = self.clone_meters();
                                 tx.send(meters).unwrap();
                             }
+                            ControlMessage::SnapshotHistory(windows, tx) => {
+                                let history = self.clone_history(windows);
+                                tx.send(history).unwrap();
+                            }
+                            ControlMessage::SnapshotBuckets(tx) => {
+                                tx.send(self.clone_buckets()).unwrap();
+                            }
+                            ControlMessage::RenderWaterfall(name, tx) => {
+                                let _ = tx.send(self.render_waterfall(&name));
+                            }
+                            ControlMessage::RenderTrace(name, tx) => {
+                                let _ = tx.send(self.render_trace(&name));
+                            }
+                            ControlMessage::RenderSvg(name, html, tx) => {
+                                let _ = tx.send(self.render_svg(&name, html));
+                            }
                         }
                     }
                 }
@@ -288,7 +645,20 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
     fn check_elapsed(&mut self, t1: u64) -> bool {
         let tsc = self.clocksource.counter();
         if tsc >= t1 {
-            self.meters.clear();
+            for (key, histogram) in &self.latency_histograms.data {
+                let dropped = self.latency_rollups.record(key, histogram);
+                if dropped > 0 {
+                    warn!("{} samples of {} are out of range of its rollups", dropped, key);
+                }
+            }
+            for (key, histogram) in &self.value_histograms.data {
+                let dropped = self.value_rollups.record(key, histogram);
+                if dropped > 0 {
+                    warn!("{} samples of {} are out of range of its rollups", dropped, key);
+                }
+            }
+
+            let previous = mem::replace(&mut self.meters, Meters::new());
             for interest in &self.interests {
                 match *interest {
                     Interest::Count(ref key) => {
@@ -304,27 +674,110 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
                         );
                     }
                     Interest::LatencyPercentile(ref key) => {
+                        let empty = self.latency_histograms.entries(key.clone()) == 0;
                         for percentile in self.percentiles.clone() {
-                            self.meters.set_latency_percentile(
-                                key.clone(),
-                                percentile.clone(),
+                            let value = if empty {
+                                self.retained(
+                                    &previous,
+                                    key,
+                                    &MeterKind::LatencyPercentile(percentile.0.clone()),
+                                )
+                            } else {
                                 self.latency_histograms
                                     .percentile(key.clone(), percentile.1)
-                                    .unwrap_or(0),
-                            );
+                                    .ok()
+                            };
+                            if let Some(value) = value {
+                                self.meters.set_latency_percentile(
+                                    key.clone(),
+                                    percentile.clone(),
+                                    value,
+                                );
+                            }
+                            for (i, (horizon, _)) in
+                                self.latency_rollups.horizons().iter().enumerate()
+                            {
+                                let value = if self.latency_rollups.entries(key, i) == 0 {
+                                    self.retained(
+                                        &previous,
+                                        key,
+                                        &MeterKind::LatencyRollup(
+                                            percentile.0.clone(),
+                                            horizon.clone(),
+                                        ),
+                                    )
+                                } else {
+                                    self.latency_rollups.percentile(key, i, percentile.1).ok()
+                                };
+                                if let Some(value) = value {
+                                    self.meters.set_latency_rollup(
+                                        key.clone(),
+                                        percentile.clone(),
+                                        horizon,
+                                        value,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    Interest::LatencyPercentileCumulative(ref key) => {
+                        for percentile in self.percentiles.clone() {
+                            if let Ok(value) =
+                                self.cumulative_histograms.percentile(key.clone(), percentile.1)
+                            {
+                                self.meters.set_latency_cumulative(key.clone(), percentile, value);
+                            }
                         }
                     }
                     Interest::ValuePercentile(ref key) => {
+                        let empty = self.value_histograms.entries(key.clone()) == 0;
                         for percentile in self.percentiles.clone() {
-                            self.meters.set_value_percentile(
-                                key.clone(),
-                                percentile.clone(),
-                                (self.value_histograms
-                                     .percentile(key.clone(), percentile.1)
-                                     .unwrap_or(0) as f64 *
-                                     self.config.sample_rate) as
-                                    u64,
-                            );
+                            let value = if empty {
+                                self.retained(
+                                    &previous,
+                                    key,
+                                    &MeterKind::ValuePercentile(percentile.0.clone()),
+                                )
+                            } else {
+                                self.value_histograms
+                                    .percentile(key.clone(), percentile.1)
+                                    .ok()
+                                    .map(|v| (v as f64 * self.config.sample_rate) as u64)
+                            };
+                            if let Some(value) = value {
+                                self.meters.set_value_percentile(
+                                    key.clone(),
+                                    percentile.clone(),
+                                    value,
+                                );
+                            }
+                            for (i, (horizon, _)) in
+                                self.value_rollups.horizons().iter().enumerate()
+                            {
+                                let value = if self.value_rollups.entries(key, i) == 0 {
+                                    self.retained(
+                                        &previous,
+                                        key,
+                                        &MeterKind::ValueRollup(
+                                            percentile.0.clone(),
+                                            horizon.clone(),
+                                        ),
+                                    )
+                                } else {
+                                    self.value_rollups
+                                        .percentile(key, i, percentile.1)
+                                        .ok()
+                                        .map(|v| (v as f64 * self.config.sample_rate) as u64)
+                                };
+                                if let Some(value) = value {
+                                    self.meters.set_value_rollup(
+                                        key.clone(),
+                                        percentile.clone(),
+                                        horizon,
+                                        value,
+                                    );
+                                }
+                            }
                         }
                     }
                     Interest::AllanDeviation(ref key) => {
@@ -338,55 +791,306 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
                 }
             }
 
+            for (key, count) in self.labelled_counters.iter() {
+                self.meters.set_labelled(
+                    key.0.clone(),
+                    key.1.clone(),
+                    MeterKind::Count,
+                    Reading::Integer(*count),
+                );
+            }
+            // labelled percentiles are only reported for windows with samples
+            for (key, histogram) in &self.labelled_histograms.data {
+                if histogram.entries() == 0 {
+                    continue;
+                }
+                for percentile in &self.percentiles {
+                    if let Ok(value) = histogram.percentile(percentile.1) {
+                        self.meters.set_labelled(
+                            key.0.clone(),
+                            key.1.clone(),
+                            MeterKind::LatencyPercentile(percentile.0.clone()),
+                            Reading::Integer(value),
+                        );
+                    }
+                }
+            }
+
+            let t0 = t1.saturating_sub(self.window_duration);
+            let start = self.unix_time(t0);
+            let end = self.unix_time(t1);
+            self.meters.set_window(Window {
+                index: self.window_index,
+                start: start,
+                end: end,
+                start_counter: t0,
+                end_counter: t1,
+                duration: end.saturating_sub(start),
+                samples: self.samples,
+                batches: self.batches,
+                labels_dropped: self.labels_dropped,
+                auto_rejected: self.auto_rejected,
+            });
+            self.meters.set_receiver_meter("tic_artifact_errors", self.artifact_errors);
+            {
+                let histograms =
+                    WindowHistograms::new(&self.latency_histograms, &self.value_histograms);
+                for sink in &mut self.sinks {
+                    if let Err(e) = sink.window(self.meters.window(), &self.meters) {
+                        error!("sink failed at end of window: {}", e);
+                    }
+                    if let Err(e) = sink.histograms(self.meters.window(), &histograms) {
+                        error!("sink failed to record histograms: {}", e);
+                    }
+                }
+            }
+
+            if self.config.history > 0 {
+                if self.history.len() >= self.config.history {
+                    self.history.pop_front();
+                }
+                self.history.push_back(self.meters.clone());
+            }
+
             self.latency_histograms.clear();
             self.value_histograms.clear();
+            self.labelled_histograms.clear();
             self.window_time += self.window_duration;
+            self.window_index += 1;
             return true;
         }
         false
     }
 
+    // the value to report for a windowed meter of a metric which had no
+    // samples in the window, according to the `Retention` policy
+    fn retained(&self, previous: &Meters<T>, key: &T, kind: &MeterKind) -> Option<u64> {
+        match self.config.retention {
+            Retention::Zero => Some(0),
+            Retention::Last => {
+                match previous.get(key, kind) {
+                    Some(&Reading::Integer(value)) => Some(value),
+                    _ => None,
+                }
+            }
+            Retention::Omit => None,
+        }
+    }
+
+    // convert a past counter value into nanoseconds since the unix epoch
+    fn unix_time(&self, counter: u64) -> u64 {
+        let now = self.clocksource.counter();
+        let elapsed = self.clocksource.convert(now) - self.clocksource.convert(counter);
+        (common::unix_time() as f64 - elapsed) as u64
+    }
+
     /// run the receive loop for all windows, output waterfall and traces as requested
     pub fn run(&mut self) {
+        self.run_with(|_, _| {});
+    }
+
+    /// run the receive loop for all windows, output waterfall and traces as
+    /// requested, and call the given function with the `Window` and freshly
+    /// calculated `Meters` as each window closes
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use tic::Receiver;
+    /// let mut receiver = Receiver::<usize>::new();
+    /// receiver.run_with(|window, meters| {
+    ///     println!("window: {} count: {:?}", window.index, meters.count(&0));
+    /// });
+    /// ```
+    pub fn run_with<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&Window, &Meters<T>),
+    {
         let mut window = 0;
         debug!("collection ready");
         'outer: loop {
             'inner: loop {
                 self.run_once();
+                callback(self.meters.window(), &self.meters);
                 window += 1;
                 if window >= self.config.windows {
+                    window = 0;
                     break 'inner;
                 }
             }
 
-            self.save_files();
+            // failures are logged and counted by `save_files`, and passed to
+            // the sinks with the run
+            let errors = self.save_files().err().unwrap_or_default();
+
+            {
+                let run = Run::new(
+                    self.run_index,
+                    &self.latency_heatmaps,
+                    &self.value_heatmaps,
+                    &errors,
+                );
+                for sink in &mut self.sinks {
+                    if let Err(e) = sink.run(&run) {
+                        error!("sink failed at end of run: {}", e);
+                    }
+                }
+            }
+            self.run_index += 1;
 
             if !self.config.service_mode {
+                self.log_cumulative();
                 break 'outer;
             } else {
-                self.clear_heatmaps();
+                if !self.config.rolling_heatmaps {
+                    self.clear_heatmaps();
+                }
                 self.end_time += self.run_duration;
             }
         }
     }
 
-    /// save all artifacts
-    pub fn save_files(&mut self) {
+    // log the lifetime percentiles of each metric with a cumulative interest
+    fn log_cumulative(&self) {
+        for interest in &self.interests {
+            if let Interest::LatencyPercentileCumulative(ref key) = *interest {
+                let percentiles: Vec<String> = self.percentiles
+                    .iter()
+                    .filter_map(|p| {
+                        self.meters.latency_cumulative(key, p.clone()).map(|v| {
+                            format!("{}: {}", p.0, v)
+                        })
+                    })
+                    .collect();
+                info!("{} latency (ns) over run: {}", key, percentiles.join(" "));
+            }
+        }
+    }
+
+    /// save all artifacts, returning each artifact which failed to save
+    ///
+    /// failures are also logged, and counted by the `tic_artifact_errors`
+    /// meter of the `Receiver`
+    pub fn save_files(&mut self) -> Result<(), Vec<ArtifactError>> {
+        self.roll_heatmaps();
+        let placeholders = Placeholders::now(self.window_index, self.run_index);
+        let mut errors = Vec::new();
         for interest in self.interests.clone() {
-            match interest {
+            let (template, path, result) = match interest {
                 Interest::LatencyTrace(l, f) => {
-                    self.latency_heatmaps.trace(l, f);
+                    let path = self.artifacts.path(&f, &placeholders);
+                    let heatmaps = &self.latency_heatmaps;
+                    let result = self.artifacts.save(&f, path.clone(), |file| {
+                        heatmaps.trace(l, file)
+                    });
+                    (f, path, result)
                 }
                 Interest::ValueTrace(l, f) => {
-                    self.value_heatmaps.trace(l, f);
+                    let path = self.artifacts.path(&f, &placeholders);
+                    let heatmaps = &self.value_heatmaps;
+                    let result = self.artifacts.save(&f, path.clone(), |file| {
+                        heatmaps.trace(l, file)
+                    });
+                    (f, path, result)
+                }
+                Interest::LatencyWaterfall(l, f, o) => {
+                    let path = self.artifacts.path(&f, &placeholders);
+                    let heatmaps = &self.latency_heatmaps;
+                    let result = self.artifacts.save(&f, path.clone(), |file| {
+                        heatmaps.waterfall(l, file, &o)
+                    });
+                    (f, path, result)
+                }
+                Interest::LatencySvg(l, f) => {
+                    let path = self.artifacts.path(&f, &placeholders);
+                    let heatmaps = &self.latency_heatmaps;
+                    let result = self.artifacts.save(&f, path.clone(), |file| {
+                        heatmaps.svg(l, file)
+                    });
+                    (f, path, result)
+                }
+                Interest::LatencySeries(l, f) => {
+                    let path = self.artifacts.path(&f, &placeholders);
+                    let heatmaps = &self.latency_heatmaps;
+                    let percentiles = &self.percentiles;
+                    let result = self.artifacts.save(&f, path.clone(), |file| {
+                        heatmaps.series(l, file, percentiles)
+                    });
+                    (f, path, result)
                 }
-                Interest::LatencyWaterfall(l, f) => {
-                    self.latency_heatmaps.waterfall(l, f);
+                Interest::ValueWaterfall(l, f, o) => {
+                    let path = self.artifacts.path(&f, &placeholders);
+                    let heatmaps = &self.value_heatmaps;
+                    let result = self.artifacts.save(&f, path.clone(), |file| {
+                        heatmaps.waterfall(l, file, &o)
+                    });
+                    (f, path, result)
                 }
-                Interest::ValueWaterfall(l, f) => {
-                    self.value_heatmaps.waterfall(l, f);
+                _ => continue,
+            };
+            if let Err(e) = result {
+                errors.push(ArtifactError::new(template, path, e));
+            }
+        }
+        self.save_defaults(&placeholders, &mut errors);
+
+        for e in &errors {
+            error!("{}", e);
+        }
+        self.artifact_errors += errors.len() as u64;
+        self.meters.set_receiver_meter("tic_artifact_errors", self.artifact_errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // save the `Config::trace_file` and `Config::waterfall_file` of each
+    // metric with a `LatencyPercentile` interest
+    fn save_defaults(&mut self, placeholders: &Placeholders, errors: &mut Vec<ArtifactError>) {
+        for interest in self.interests.clone() {
+            let key = match interest {
+                Interest::LatencyPercentile(key) => key,
+                _ => continue,
+            };
+            let metric = key.to_string();
+            let mut trace = false;
+            let mut waterfall = false;
+            for other in &self.interests {
+                match *other {
+                    Interest::LatencyTrace(ref k, _) if *k == key => trace = true,
+                    Interest::LatencyWaterfall(ref k, _, _) if *k == key => waterfall = true,
+                    _ => {}
+                }
+            }
+
+            if let Some(file) = self.config.trace_file.clone() {
+                if !trace {
+                    let template = artifacts::for_metric(&file, &metric);
+                    let path = self.artifacts.path(&template, placeholders);
+                    let heatmaps = &self.latency_heatmaps;
+                    let k = key.clone();
+                    let result = self.artifacts.save(&template, path.clone(), |file| {
+                        heatmaps.trace(k, file)
+                    });
+                    if let Err(e) = result {
+                        errors.push(ArtifactError::new(template, path, e));
+                    }
+                }
+            }
+
+            if let Some(file) = self.config.waterfall_file.clone() {
+                if !waterfall {
+                    let template = artifacts::for_metric(&file, &metric);
+                    let path = self.artifacts.path(&template, placeholders);
+                    let heatmaps = &self.latency_heatmaps;
+                    let result = self.artifacts.save(&template, path.clone(), |file| {
+                        heatmaps.waterfall(key, file, &WaterfallOptions::default())
+                    });
+                    if let Err(e) = result {
+                        errors.push(ArtifactError::new(template, path, e));
+                    }
                 }
-                _ => {}
             }
         }
     }
@@ -395,6 +1099,108 @@ impl<T: Hash + Eq + Send + Display + Clone> Receiver<T> {
     pub fn clone_meters(&self) -> Meters<T> {
         self.meters.clone()
     }
+
+    /// return a clone of the `Meters` from up to the given number of the most
+    /// recent windows, oldest first
+    pub fn clone_history(&self, windows: usize) -> Vec<Meters<T>> {
+        let skip = self.history.len().saturating_sub(windows);
+        self.history.iter().skip(skip).cloned().collect()
+    }
+
+    /// return the raw buckets of the lifetime latency histogram of each metric
+    /// with a `LatencyPercentileCumulative` interest, keyed by the `Display`
+    /// of the metric
+    pub fn clone_buckets(&self) -> Buckets {
+        let mut buckets = Buckets::new();
+        for (key, histogram) in &self.cumulative_histograms.data {
+            buckets.add_histogram(&key.to_string(), histogram);
+        }
+        buckets
+    }
+
+    /// render a PNG waterfall of the current latency heatmap for the metric
+    /// with the given name, using the options of its `LatencyWaterfall`
+    /// interest if it has one
+    pub fn render_waterfall(&mut self, name: &str) -> Result<Vec<u8>, io::Error> {
+        self.roll_heatmaps();
+        match self.latency_heatmaps.find(name) {
+            Some(key) => {
+                let options = self.interests
+                    .iter()
+                    .filter_map(|interest| match *interest {
+                        Interest::LatencyWaterfall(ref k, _, ref o) if *k == key => Some(o),
+                        _ => None,
+                    })
+                    .next()
+                    .cloned()
+                    .unwrap_or_default();
+                self.latency_heatmaps.render_waterfall(key, &options)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no latency heatmap for metric",
+            )),
+        }
+    }
+
+    /// render a trace of the current latency heatmap for the metric with the
+    /// given name
+    pub fn render_trace(&mut self, name: &str) -> Result<Vec<u8>, io::Error> {
+        self.roll_heatmaps();
+        match self.latency_heatmaps.find(name) {
+            Some(key) => self.latency_heatmaps.render_trace(key),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no latency heatmap for metric",
+            )),
+        }
+    }
+
+    /// render an SVG of the current latency heatmap for the metric with the
+    /// given name, optionally wrapped in an HTML page
+    pub fn render_svg(&mut self, name: &str, html: bool) -> Result<String, io::Error> {
+        self.roll_heatmaps();
+        match self.latency_heatmaps.find(name) {
+            Some(key) => self.latency_heatmaps.render_svg(key, html),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no latency heatmap for metric",
+            )),
+        }
+    }
+}
+
+// the error for two artifacts which would be saved to the same path
+fn collision(first: &str, second: &str, path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} and {} are both saved to {}", first, second, path),
+    )
+}
+
+// check that the default trace and waterfall do not overwrite each other
+fn check_defaults<T>(config: &Config<T>) -> Result<(), io::Error> {
+    match config.trace_file {
+        Some(ref file) if config.waterfall_file.as_ref() == Some(file) => Err(collision(
+            "Config::trace_file",
+            "Config::waterfall_file",
+            file,
+        )),
+        _ => Ok(()),
+    }
+}
+
+// the kind, metric and file of an interest which saves an artifact
+fn artifact<T>(interest: &Interest<T>) -> Option<(&'static str, &T, &str)> {
+    match *interest {
+        Interest::LatencyTrace(ref key, ref file) => Some(("LatencyTrace", key, file)),
+        Interest::LatencyWaterfall(ref key, ref file, _) => Some(("LatencyWaterfall", key, file)),
+        Interest::LatencySvg(ref key, ref file) => Some(("LatencySvg", key, file)),
+        Interest::LatencySeries(ref key, ref file) => Some(("LatencySeries", key, file)),
+        Interest::ValueTrace(ref key, ref file) => Some(("ValueTrace", key, file)),
+        Interest::ValueWaterfall(ref key, ref file, _) => Some(("ValueWaterfall", key, file)),
+        _ => None,
+    }
 }
 
 #[cfg(feature = "benchmark")]
@@ -427,3 +1233,463 @@ mod benchmark {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sink::Sink;
+    use std::env;
+    use std::fs::{self, File};
+    use std::process;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn p50() -> Percentile {
+        Percentile("p50".to_owned(), 50.0)
+    }
+
+    fn receiver(retention: Retention) -> Receiver<String> {
+        Receiver::configured(Receiver::configure().retention(retention))
+    }
+
+    #[test]
+    fn remove_adev() {
+        let ok = "ok".to_owned();
+        let mut receiver = receiver(Retention::Zero);
+        receiver.add_interest(Interest::AllanDeviation(ok.clone()));
+        for _ in 0..10 {
+            receiver.allans.record(ok.clone(), 1000.0);
+        }
+        receiver.check_elapsed(0);
+        assert!(receiver.meters.adev(ok.clone(), 1).is_some());
+
+        receiver.remove_interest(&Interest::AllanDeviation(ok.clone()));
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.adev(ok.clone(), 1), None);
+        assert_eq!(receiver.meters.iter().count(), 0);
+    }
+
+    #[test]
+    fn add_between_windows() {
+        let ok = "ok".to_owned();
+        let mut receiver = receiver(Retention::Zero);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.count(&ok), None);
+
+        receiver.add_interest(Interest::Count(ok.clone()));
+        receiver.counters.increment_by(ok.clone(), 3);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.count(&ok), Some(&3));
+    }
+
+    #[test]
+    fn wildcard() {
+        let ok = "ok".to_owned();
+        let error = "error".to_owned();
+        let mut receiver = Receiver::configured(Receiver::configure().auto_channels(1));
+        receiver.add_interest(Interest::AllCount);
+        assert!(receiver.auto_register(&ok, (true, false)));
+        receiver.counters.increment_by(ok.clone(), 3);
+        assert!(!receiver.auto_register(&error, (true, false)));
+        receiver.counters.increment_by(error.clone(), 1);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.count(&ok), Some(&3));
+        assert_eq!(receiver.meters.count(&error), None);
+        assert_eq!(receiver.meters.window().auto_rejected, 1);
+
+        receiver.add_interest(Interest::AllLatencyPercentile);
+        assert!(receiver.interests.contains(&Interest::LatencyPercentile(ok.clone())));
+        receiver.remove_interest(&Interest::AllCount);
+        receiver.remove_interest(&Interest::AllLatencyPercentile);
+        assert!(receiver.interests.is_empty());
+
+        // the limit is freed once no wildcard is left
+        receiver.add_interest(Interest::Count(error.clone()));
+        receiver.add_interest(Interest::AllCount);
+        receiver.auto_register(&error, (true, false));
+        receiver.remove_interest(&Interest::AllCount);
+        assert!(receiver.interests.contains(&Interest::Count(error)));
+    }
+
+    #[test]
+    fn wildcard_samples() {
+        let config = Receiver::configure()
+            .duration(1)
+            .batch_size(4)
+            .auto_channels(1)
+            .poll_delay(Some(Duration::from_millis(10)));
+        let mut receiver = Receiver::configured(config);
+        receiver.add_interest(Interest::AllCount);
+        let mut sender = receiver.get_sender();
+        for channel in &["ok", "ok", "error", "error"] {
+            sender.send(Sample::new(0, 100, channel.to_string())).unwrap();
+        }
+        receiver.run_once();
+
+        let window = receiver.meters.window();
+        assert_eq!((window.samples, window.auto_rejected), (4, 2));
+        assert_eq!(receiver.meters.count(&"ok".to_owned()), Some(&2));
+        assert_eq!(receiver.meters.count(&"error".to_owned()), None);
+    }
+
+    #[test]
+    fn history() {
+        let ok = "ok".to_owned();
+        let mut receiver = Receiver::configured(Receiver::configure().history(2));
+        receiver.add_interest(Interest::Count(ok.clone()));
+        for _ in 0..3 {
+            receiver.counters.increment(ok.clone());
+            receiver.check_elapsed(0);
+        }
+
+        let history = receiver.clone_history(10);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].count(&ok), Some(&2));
+        assert_eq!(history[1].count(&ok), Some(&3));
+        assert_eq!(history[1].window().index, 2);
+
+        let history = receiver.clone_history(1);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].count(&ok), Some(&3));
+    }
+
+    #[test]
+    fn rollups() {
+        let ok = "ok".to_owned();
+        let p100 = Percentile("p100".to_owned(), 100.0);
+        let config = Receiver::configure().duration(1).rollup(2);
+        let mut receiver = Receiver::configured(config);
+        receiver.percentiles = vec![p100.clone()];
+        receiver.add_interest(Interest::LatencyPercentile(ok.clone()));
+
+        receiver.latency_histograms.increment(ok.clone(), 300);
+        receiver.check_elapsed(0);
+        receiver.latency_histograms.increment(ok.clone(), 100);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_percentile(&ok, p100.clone()), Some(&100));
+        assert_eq!(receiver.meters.latency_rollup(&ok, p100.clone(), "2s"), Some(&300));
+
+        receiver.latency_histograms.increment(ok.clone(), 200);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_rollup(&ok, p100.clone(), "2s"), Some(&200));
+    }
+
+    #[test]
+    fn window_histogram_config() {
+        let ok = "ok".to_owned();
+        let p100 = Percentile("p100".to_owned(), 100.0);
+        let mut config = Receiver::configure().duration(1).rollup(2);
+        config.histogram_config = histogram::Histogram::configure()
+            .max_value(10 * common::MINUTE)
+            .precision(4);
+        let mut receiver = Receiver::configured(config);
+        receiver.percentiles = vec![p100.clone()];
+        receiver.add_interest(Interest::LatencyPercentile(ok.clone()));
+
+        // beyond the default range, and only kept exactly with the precision
+        // of the config
+        let value = 2 * common::MINUTE + 12_345_678;
+        receiver.latency_histograms.increment(ok.clone(), value);
+        receiver.check_elapsed(0);
+        let window = *receiver.meters.latency_percentile(&ok, p100.clone()).unwrap();
+        assert!(window >= value && window - value < value / 10_000);
+        assert!(receiver.meters.latency_rollup(&ok, p100, "2s").is_some());
+    }
+
+    #[test]
+    fn cumulative() {
+        let ok = "ok".to_owned();
+        let p100 = Percentile("p100".to_owned(), 100.0);
+        let mut receiver = receiver(Retention::Zero);
+        receiver.percentiles = vec![p100.clone()];
+        receiver.add_interest(Interest::LatencyPercentileCumulative(ok.clone()));
+        receiver.add_interest(Interest::LatencyPercentile(ok.clone()));
+
+        receiver.latency_histograms.increment(ok.clone(), 300);
+        receiver.cumulative_histograms.increment(ok.clone(), 300);
+        receiver.check_elapsed(0);
+        receiver.latency_histograms.increment(ok.clone(), 100);
+        receiver.cumulative_histograms.increment(ok.clone(), 100);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_percentile(&ok, p100.clone()), Some(&100));
+        assert_eq!(receiver.meters.latency_cumulative(&ok, p100.clone()), Some(&300));
+
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_cumulative(&ok, p100.clone()), Some(&300));
+
+        let buckets = receiver.clone_buckets();
+        assert_eq!(buckets.entries("ok"), 2);
+        assert_eq!(buckets.percentile("ok", 100.0), Some(300));
+
+        // the lifetime histogram belongs to the cumulative interest only
+        receiver.remove_interest(&Interest::LatencyPercentile(ok.clone()));
+        assert_eq!(receiver.clone_buckets().entries("ok"), 2);
+        receiver.remove_interest(&Interest::LatencyPercentileCumulative(ok.clone()));
+        assert_eq!(receiver.clone_buckets().metrics(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn percentile_without_cumulative() {
+        let ok = "ok".to_owned();
+        let mut receiver = receiver(Retention::Zero);
+        receiver.add_interest(Interest::LatencyPercentile(ok.clone()));
+        assert!(!receiver.cumulative_histograms.contains(&ok));
+        receiver.cumulative_histograms.increment(ok.clone(), 100);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.clone_buckets().metrics(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn retention_zero() {
+        let ok = "ok".to_owned();
+        let mut receiver = receiver(Retention::Zero);
+        receiver.add_interest(Interest::LatencyPercentile(ok.clone()));
+        receiver.latency_histograms.increment(ok.clone(), 100);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_percentile(&ok, p50()), Some(&100));
+
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_percentile(&ok, p50()), Some(&0));
+    }
+
+    #[test]
+    fn retention_last() {
+        let ok = "ok".to_owned();
+        let mut receiver = receiver(Retention::Last);
+        receiver.add_interest(Interest::LatencyPercentile(ok.clone()));
+        receiver.latency_histograms.increment(ok.clone(), 100);
+        receiver.check_elapsed(0);
+        receiver.check_elapsed(0);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_percentile(&ok, p50()), Some(&100));
+    }
+
+    #[test]
+    fn retention_omit() {
+        let ok = "ok".to_owned();
+        let mut receiver = receiver(Retention::Omit);
+        receiver.add_interest(Interest::LatencyPercentile(ok.clone()));
+        receiver.add_interest(Interest::Count(ok.clone()));
+        receiver.latency_histograms.increment(ok.clone(), 100);
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_percentile(&ok, p50()), Some(&100));
+
+        receiver.check_elapsed(0);
+        assert_eq!(receiver.meters.latency_percentile(&ok, p50()), None);
+        assert_eq!(receiver.meters.count(&ok), Some(&0));
+    }
+
+    // reports the index of each window, and of each run as true
+    struct Boundaries(mpsc::Sender<(usize, bool)>);
+
+    impl Sink<String> for Boundaries {
+        fn window(&mut self, window: &Window, _: &Meters<String>) -> Result<(), io::Error> {
+            let _ = self.0.send((window.index, false));
+            Ok(())
+        }
+
+        fn run(&mut self, run: &Run<String>) -> Result<(), io::Error> {
+            let _ = self.0.send((run.index(), true));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn service_mode_runs() {
+        let (tx, rx) = mpsc::channel();
+        let config = Receiver::configure()
+            .duration(1)
+            .windows(2)
+            .service(true)
+            .poll_delay(Some(Duration::from_millis(10)))
+            .sink(move || Box::new(Boundaries(tx.clone())));
+        thread::spawn(move || Receiver::configured(config).run());
+
+        // every run spans all its windows, not only the first
+        let mut boundaries = Vec::new();
+        while boundaries.last() != Some(&(1, true)) {
+            boundaries.push(rx.recv().unwrap());
+        }
+        assert_eq!(
+            boundaries,
+            vec![(0, false), (1, false), (0, true), (2, false), (3, false), (1, true)]
+        );
+    }
+
+    // reports the number of windows it was called for
+    struct Calls(usize, mpsc::Sender<usize>);
+
+    impl Sink<String> for Calls {
+        fn window(&mut self, _: &Window, _: &Meters<String>) -> Result<(), io::Error> {
+            self.0 += 1;
+            let _ = self.1.send(self.0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cloned_config_builds_own_sinks() {
+        let (tx, rx) = mpsc::channel();
+        let config = Receiver::<String>::configure().sink(move || Box::new(Calls(0, tx.clone())));
+        let mut first = Receiver::configured(config.clone());
+        let mut second = Receiver::configured(config);
+
+        // a shared sink would count the window of the second receiver as its
+        // second call
+        first.check_elapsed(0);
+        second.check_elapsed(0);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(1));
+        first.check_elapsed(0);
+        assert_eq!(rx.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn label_slots() {
+        let ok = "ok".to_owned();
+        let config = Receiver::configure().label_cardinality(2);
+        let mut receiver = Receiver::configured(config);
+        let sample = |status| Sample::new(0, 100, ok.clone()).with_label("status", status);
+        receiver.add_interest(Interest::Count(ok.clone()));
+        receiver.record_labelled(&sample("200"), 100);
+        receiver.add_interest(Interest::LatencyPercentile(ok.clone()));
+        receiver.record_labelled(&sample("500"), 100);
+        receiver.record_labelled(&sample("503"), 100);
+        assert_eq!(receiver.labels_dropped, 1);
+
+        // only the set which was never timed frees its slot
+        receiver.remove_interest(&Interest::Count(ok.clone()));
+        receiver.add_interest(Interest::Count(ok.clone()));
+        receiver.record_labelled(&sample("500"), 100);
+        assert_eq!(receiver.labels_dropped, 1);
+        receiver.record_labelled(&sample("503"), 100);
+        assert_eq!(receiver.labels_dropped, 1);
+        receiver.record_labelled(&sample("504"), 100);
+        assert_eq!(receiver.labels_dropped, 2);
+
+        // all slots are freed once neither interest remains
+        receiver.remove_interest(&Interest::Count(ok.clone()));
+        receiver.remove_interest(&Interest::LatencyPercentile(ok.clone()));
+        assert!(receiver.label_series.is_empty());
+    }
+
+    // reports the number of artifacts which failed to save in each run
+    struct Failures(mpsc::Sender<usize>);
+
+    impl Sink<String> for Failures {
+        fn window(&mut self, _: &Window, _: &Meters<String>) -> Result<(), io::Error> {
+            Ok(())
+        }
+
+        fn run(&mut self, run: &Run<String>) -> Result<(), io::Error> {
+            let _ = self.0.send(run.artifact_errors().len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn final_artifact_errors() {
+        // the artifact directory is a file, so no artifact can be saved
+        let blocked = env::temp_dir().join(format!("tic_blocked_{}", process::id()));
+        File::create(&blocked).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let config = Receiver::configure()
+            .duration(1)
+            .windows(1)
+            .poll_delay(Some(Duration::from_millis(10)))
+            .artifact_directory(&blocked)
+            .sink(move || Box::new(Failures(tx.clone())));
+        let mut receiver = Receiver::configured(config);
+        receiver.add_interest(Interest::LatencyTrace("ok".to_owned(), "ok.txt".to_owned()));
+        receiver.run();
+        let _ = fs::remove_file(&blocked);
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(receiver.meters.receiver_meter("tic_artifact_errors"), Some(&1));
+    }
+
+    #[test]
+    fn unwritable_trace() {
+        // the temporary file of the trace is a directory, so it can't be written
+        let directory = env::temp_dir().join(format!("tic_unwritable_{}", process::id()));
+        fs::create_dir_all(directory.join(".tmp_ok.txt")).unwrap();
+
+        let config = Receiver::configure().artifact_directory(&directory);
+        let mut receiver = Receiver::configured(config);
+        receiver.add_interest(Interest::LatencyTrace("ok".to_owned(), "ok.txt".to_owned()));
+        let errors = receiver.save_files().unwrap_err();
+        let _ = fs::remove_dir_all(&directory);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(receiver.meters.receiver_meter("tic_artifact_errors"), Some(&1));
+    }
+
+    #[test]
+    fn same_trace_and_waterfall_file() {
+        let result = Receiver::<String>::configure()
+            .trace_file("ok.out".to_owned())
+            .waterfall_file("ok.out".to_owned())
+            .try_build();
+        let e = result.err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            e.to_string(),
+            "Config::trace_file and Config::waterfall_file are both saved to ok.out"
+        );
+
+        // the infallible build keeps the trace only
+        let receiver = Receiver::<String>::configure()
+            .trace_file("ok.out".to_owned())
+            .waterfall_file("ok.out".to_owned())
+            .build();
+        assert_eq!(receiver.config.trace_file, Some("ok.out".to_owned()));
+        assert_eq!(receiver.config.waterfall_file, None);
+    }
+
+    #[test]
+    fn colliding_interest_files() {
+        let mut receiver = Receiver::<String>::configure()
+            .trace_file("{metric}.out".to_owned())
+            .waterfall_file("{metric}.png".to_owned())
+            .build();
+        let ok = "ok".to_owned();
+
+        let e = receiver
+            .try_add_interest(Interest::LatencyTrace(ok.clone(), "ok.png".to_owned()))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            e.to_string(),
+            "LatencyTrace of ok and Config::waterfall_file are both saved to ok.png"
+        );
+
+        // a different path only replaces the default of the metric
+        receiver
+            .try_add_interest(Interest::LatencyTrace(ok.clone(), "trace.out".to_owned()))
+            .unwrap();
+        // adding the same interest again is fine
+        receiver
+            .try_add_interest(Interest::LatencyTrace(ok.clone(), "trace.out".to_owned()))
+            .unwrap();
+        let e = receiver
+            .try_add_interest(Interest::ValueTrace(ok.clone(), "trace.out".to_owned()))
+            .err()
+            .unwrap();
+        assert_eq!(
+            e.to_string(),
+            "ValueTrace of ok and LatencyTrace of ok are both saved to trace.out"
+        );
+
+        // the infallible version ignores the interest
+        receiver.add_interest(Interest::LatencySvg(ok.clone(), "ok.out".to_owned()));
+        assert!(!receiver
+            .interests
+            .contains(&Interest::LatencySvg(ok.clone(), "ok.out".to_owned())));
+        assert!(!receiver
+            .interests
+            .contains(&Interest::ValueTrace(ok, "trace.out".to_owned())));
+    }
+}
@@ -0,0 +1,69 @@
+// a 3x5 bitmap font for the axis labels and legend of waterfalls, covering
+// the digits and the few letters of the units and time labels
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+pub const GLYPH_ADVANCE: usize = GLYPH_WIDTH + 1;
+
+// the rows of a 3x5 glyph, the high bit of each row is the leftmost pixel
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'h' => [0b100, 0b100, 0b111, 0b101, 0b101],
+        'k' => [0b100, 0b101, 0b110, 0b110, 0b101],
+        'm' => [0b000, 0b111, 0b111, 0b101, 0b101],
+        'n' => [0b000, 0b110, 0b101, 0b101, 0b101],
+        'p' => [0b000, 0b111, 0b101, 0b111, 0b100],
+        's' => [0b000, 0b011, 0b100, 0b001, 0b110],
+        'u' => [0b000, 0b101, 0b101, 0b101, 0b111],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::horizon_label;
+    use render::{format_count, format_nanoseconds};
+
+    // the characters which the labels and legends are made of
+    const CHARSET: &str = "0123456789.GMhkmnpsu";
+
+    #[test]
+    fn distinct_glyphs() {
+        let glyphs: Vec<[u8; GLYPH_HEIGHT]> = CHARSET.chars().map(glyph).collect();
+        for (i, g) in glyphs.iter().enumerate() {
+            assert!(g.iter().any(|row| *row != 0));
+            assert!(g.iter().all(|row| *row < 1 << GLYPH_WIDTH));
+            assert!(!glyphs[(i + 1)..].contains(g));
+        }
+        assert_eq!(glyph('?'), [0; GLYPH_HEIGHT]);
+    }
+
+    #[test]
+    fn labels_are_covered() {
+        let mut labels = vec!["p99.9".to_owned()];
+        for value in &[1, 1_000, 1_000_000, 1_000_000_000, 60_000_000_000] {
+            labels.push(format_nanoseconds(*value));
+            labels.push(format_count(*value));
+        }
+        for seconds in &[0, 30, 60, 3_600] {
+            labels.push(horizon_label(*seconds));
+        }
+        for label in labels {
+            assert!(label.chars().all(|c| CHARSET.contains(c)), "{}", label);
+        }
+    }
+}
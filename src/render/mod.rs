@@ -0,0 +1,282 @@
+// `render` draws heatmaps as PNG waterfalls and SVGs, with one column per
+// slice and one row per histogram bucket
+//
+// the `waterfall` crate used before only writes a PNG with a fixed palette to
+// a path, so it can't change the scale, clip the values, label the axes or
+// overlay percentiles, and serving an image over HTTP needed a temporary
+// file. the drawing is small enough to keep here instead: `waterfall` sets
+// the pixels of the image and the png crate encodes it, `svg` writes the same
+// layout as SVG elements, and `font` is a 3x5 bitmap font covering the few
+// characters of the axis labels, which avoids a font rendering dependency
+
+mod font;
+mod svg;
+mod waterfall;
+
+pub use self::svg::{html, svg};
+pub use self::waterfall::render;
+
+use common::SECOND;
+use heatmap::Heatmap;
+use histogram::Bucket;
+
+const BACKGROUND: [u8; 3] = [0, 0, 0];
+const FOREGROUND: [u8; 3] = [200, 200, 200];
+const OVERLAYS: [[u8; 3]; 4] = [[255, 255, 255], [255, 0, 255], [0, 255, 255], [255, 128, 0]];
+
+/// the scale which maps the count of a bucket to the intensity of its pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scale {
+    /// intensity is proportional to the count
+    Linear,
+    /// intensity is proportional to the log of the count, which keeps rare
+    /// outliers visible next to the busiest buckets
+    Log,
+}
+
+/// the colors used for the intensity of the buckets, empty buckets are black
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Palette {
+    /// blue through cyan, green and yellow to red
+    Classic,
+    /// dark gray to white
+    Grayscale,
+    /// dark red through red and yellow to white
+    Heat,
+}
+
+/// `WaterfallOptions` control how a waterfall PNG is rendered
+///
+/// by default the intensity is linear with the `Classic` palette, all buckets
+/// are drawn, the axes are labelled with a time tick every minute and there
+/// are no percentile overlays
+///
+/// # Example
+/// ```
+/// # use tic::{Palette, Scale, WaterfallOptions};
+/// let options = WaterfallOptions::default()
+///     .scale(Scale::Log)
+///     .palette(Palette::Heat)
+///     .clip(1_000, 1_000_000_000)
+///     .percentile(50.0)
+///     .percentile(99.0);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WaterfallOptions {
+    scale: Scale,
+    palette: Palette,
+    min_value: u64,
+    max_value: Option<u64>,
+    labels: bool,
+    tick_interval: u64,
+    // in millionths, so the options can be hashed
+    percentiles: Vec<u32>,
+}
+
+impl Default for WaterfallOptions {
+    fn default() -> WaterfallOptions {
+        WaterfallOptions {
+            scale: Scale::Linear,
+            palette: Palette::Classic,
+            min_value: 0,
+            max_value: None,
+            labels: true,
+            tick_interval: 60,
+            percentiles: Vec::new(),
+        }
+    }
+}
+
+impl WaterfallOptions {
+    /// set the intensity scale
+    pub fn scale(mut self, scale: Scale) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// set the palette
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// only draw the buckets with values between min and max inclusive
+    pub fn clip(mut self, min: u64, max: u64) -> Self {
+        self.min_value = min;
+        self.max_value = Some(max);
+        self
+    }
+
+    /// draw the axis labels, time ticks and percentile legend
+    pub fn labels(mut self, enabled: bool) -> Self {
+        self.labels = enabled;
+        self
+    }
+
+    /// set the number of seconds between time ticks
+    pub fn tick_interval(mut self, seconds: u64) -> Self {
+        self.tick_interval = seconds;
+        self
+    }
+
+    /// overlay a line tracing the given percentile of each slice, eg: 99.0
+    pub fn percentile(mut self, percentile: f64) -> Self {
+        self.percentiles.push((percentile * 10_000.0).round() as u32);
+        self
+    }
+
+    fn intensity(&self, count: u64, max: u64) -> f64 {
+        if count == 0 || max == 0 {
+            return 0.0;
+        }
+        match self.scale {
+            Scale::Linear => count as f64 / max as f64,
+            Scale::Log => (1.0 + count as f64).ln() / (1.0 + max as f64).ln(),
+        }
+    }
+
+    fn color(&self, intensity: f64) -> [u8; 3] {
+        if intensity <= 0.0 {
+            return BACKGROUND;
+        }
+        let stops: &[[u8; 3]] = match self.palette {
+            Palette::Classic => &[
+                [0, 0, 255],
+                [0, 255, 255],
+                [0, 255, 0],
+                [255, 255, 0],
+                [255, 0, 0],
+            ],
+            Palette::Grayscale => &[[32, 32, 32], [255, 255, 255]],
+            Palette::Heat => &[[64, 0, 0], [255, 0, 0], [255, 255, 0], [255, 255, 255]],
+        };
+        let position = intensity.min(1.0) * (stops.len() - 1) as f64;
+        let i = (position.floor() as usize).min(stops.len() - 2);
+        let fraction = position - i as f64;
+        let mut color = [0; 3];
+        for (c, value) in color.iter_mut().enumerate() {
+            let from = f64::from(stops[i][c]);
+            let to = f64::from(stops[i + 1][c]);
+            *value = (from + (to - from) * fraction).round() as u8;
+        }
+        color
+    }
+}
+
+/// the unit of the values of a heatmap, used for the value axis labels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    Nanoseconds,
+    Count,
+}
+
+// the buckets and percentiles of one slice of the heatmap
+struct Column {
+    start: u64,
+    buckets: Vec<Bucket>,
+    percentiles: Vec<Option<u64>>,
+}
+
+// the buckets of each slice within the value range of the options, and the
+// given percentiles of each slice
+fn columns(heatmap: &Heatmap, options: &WaterfallOptions, percentiles: &[f64]) -> Vec<Column> {
+    let max_value = options.max_value.unwrap_or(u64::MAX);
+    let mut columns = Vec::new();
+    for slice in heatmap {
+        let start = slice.start();
+        let histogram = slice.histogram();
+        let buckets = (&histogram)
+            .into_iter()
+            .filter(|b| b.value() >= options.min_value && b.value() <= max_value)
+            .collect();
+        let percentiles = percentiles
+            .iter()
+            .map(|p| histogram.percentile(*p).ok())
+            .collect();
+        columns.push(Column {
+            start: start,
+            buckets: buckets,
+            percentiles: percentiles,
+        });
+    }
+    columns
+}
+
+fn max_count(columns: &[Column]) -> u64 {
+    columns
+        .iter()
+        .flat_map(|c| c.buckets.iter().map(|b| b.count()))
+        .max()
+        .unwrap_or(0)
+}
+
+fn format_nanoseconds(value: u64) -> String {
+    if value >= SECOND {
+        format!("{}s", value / SECOND)
+    } else if value >= 1_000_000 {
+        format!("{}ms", value / 1_000_000)
+    } else if value >= 1_000 {
+        format!("{}us", value / 1_000)
+    } else {
+        format!("{}ns", value)
+    }
+}
+
+fn format_count(value: u64) -> String {
+    if value >= 1_000_000_000 {
+        format!("{}G", value / 1_000_000_000)
+    } else if value >= 1_000_000 {
+        format!("{}M", value / 1_000_000)
+    } else if value >= 1_000 {
+        format!("{}k", value / 1_000)
+    } else {
+        format!("{}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one minute of slices, each with buckets at 1us, about 100us and 10ms
+    pub fn heatmap() -> Heatmap {
+        let mut heatmap = Heatmap::configure()
+            .slice_duration(SECOND)
+            .num_slices(60)
+            .precision(2)
+            .start(0)
+            .build()
+            .unwrap();
+        for second in 0..60 {
+            let time = second * SECOND;
+            let _ = heatmap.increment_by(time, 1_000, 100);
+            let _ = heatmap.increment_by(time, 100_000 + second * 1_000, 10);
+            let _ = heatmap.increment_by(time, 10_000_000, 1);
+        }
+        heatmap
+    }
+
+    // the values of the buckets of each slice
+    pub fn values(heatmap: &Heatmap) -> Vec<u64> {
+        let slice = heatmap.into_iter().next().unwrap();
+        let histogram = slice.histogram();
+        (&histogram).into_iter().map(|b| b.value()).collect()
+    }
+
+    #[test]
+    fn colors() {
+        let options = WaterfallOptions::default();
+        assert_eq!(options.color(0.0), BACKGROUND);
+        assert_eq!(options.color(1.0), [255, 0, 0]);
+        assert_eq!(options.color(0.5), [0, 255, 0]);
+        assert_eq!(options.color(options.intensity(10, 10)), [255, 0, 0]);
+        let log = options.scale(Scale::Log);
+        assert!(log.intensity(1, 1000) > 0.1);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_nanoseconds(1_500_000), "1ms");
+        assert_eq!(format_count(2_500), "2k");
+    }
+}
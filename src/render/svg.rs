@@ -0,0 +1,187 @@
+// render heatmaps as self-contained SVGs and HTML pages, with the same layout
+// as the waterfalls
+
+use common::SECOND;
+use data::horizon_label;
+use heatmap::Heatmap;
+use histogram::Bucket;
+use super::{columns, format_count, format_nanoseconds, max_count, Unit, WaterfallOptions,
+            BACKGROUND, FOREGROUND};
+
+// the size of each cell of an SVG, and the room for its labels
+const CELL_WIDTH: usize = 4;
+const CELL_HEIGHT: usize = 4;
+const SVG_LEFT: usize = 48;
+const SVG_TOP: usize = 8;
+const SVG_BOTTOM: usize = 16;
+const SVG_LABEL_ROWS: usize = 8;
+
+/// render the heatmap as a self-contained SVG, with the same layout as the
+/// waterfall. each non-empty cell has a tooltip with the time of its slice,
+/// the range of its bucket and its count. only the rows between the smallest
+/// and largest non-empty buckets are drawn
+pub fn svg(heatmap: &Heatmap, options: &WaterfallOptions, unit: Unit) -> String {
+    let columns = columns(heatmap, options, &[]);
+    let max_count = max_count(&columns);
+
+    // the first and last rows with samples in any slice
+    let mut occupied: Option<(usize, usize)> = None;
+    for column in &columns {
+        for (row, bucket) in column.buckets.iter().enumerate() {
+            if bucket.count() > 0 {
+                occupied = Some(match occupied {
+                    Some((low, high)) => (low.min(row), high.max(row)),
+                    None => (row, row),
+                });
+            }
+        }
+    }
+    let (low, rows) = match occupied {
+        Some((low, high)) => (low, high - low + 1),
+        None => (0, 0),
+    };
+
+    let width = SVG_LEFT + columns.len() * CELL_WIDTH;
+    let height = SVG_TOP + rows * CELL_HEIGHT + SVG_BOTTOM;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+         viewBox=\"0 0 {0} {1}\" font-family=\"monospace\" font-size=\"10\">\n\
+         <rect width=\"{0}\" height=\"{1}\" fill=\"{2}\"/>\n\
+         <g shape-rendering=\"crispEdges\">\n",
+        width,
+        height,
+        hex(BACKGROUND)
+    );
+    let first = columns.first().map(|c| c.start).unwrap_or(0);
+    for (x, column) in columns.iter().enumerate() {
+        let seconds = column.start.saturating_sub(first) / SECOND;
+        for (row, bucket) in column.buckets.iter().enumerate().skip(low).take(rows) {
+            if bucket.count() == 0 {
+                continue;
+            }
+            svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\">\
+                 <title>+{}s\n{}\ncount {}</title></rect>\n",
+                SVG_LEFT + x * CELL_WIDTH,
+                SVG_TOP + (rows - 1 - (row - low)) * CELL_HEIGHT,
+                CELL_WIDTH,
+                CELL_HEIGHT,
+                hex(options.color(options.intensity(bucket.count(), max_count))),
+                seconds,
+                range(bucket, unit),
+                bucket.count()
+            ));
+        }
+    }
+    svg.push_str("</g>\n");
+
+    let foreground = hex(FOREGROUND);
+    if let Some(column) = columns.first() {
+        for (row, bucket) in column.buckets.iter().enumerate().skip(low).take(rows) {
+            if (row - low) % SVG_LABEL_ROWS != 0 {
+                continue;
+            }
+            let label = match unit {
+                Unit::Nanoseconds => format_nanoseconds(bucket.value()),
+                Unit::Count => format_count(bucket.value()),
+            };
+            svg.push_str(&format!(
+                "<text x=\"{}\" y=\"{}\" fill=\"{}\" text-anchor=\"end\">{}</text>\n",
+                SVG_LEFT - 4,
+                SVG_TOP + (rows - (row - low)) * CELL_HEIGHT,
+                foreground,
+                label
+            ));
+        }
+    }
+    for (x, column) in columns.iter().enumerate() {
+        let seconds = column.start.saturating_sub(first) / SECOND;
+        if options.tick_interval == 0 || seconds % options.tick_interval != 0 {
+            continue;
+        }
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" fill=\"{}\">{}</text>\n",
+            SVG_LEFT + x * CELL_WIDTH,
+            height - 4,
+            foreground,
+            horizon_label(seconds as usize)
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// wrap an SVG in a self-contained HTML page with the given title
+pub fn html(svg: &str, title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{0}</title>\n</head>\n\
+         <body style=\"background: {1}; color: {2}; font-family: monospace\">\n\
+         <h1>{0}</h1>\n{3}</body>\n</html>\n",
+        escape(title),
+        hex(BACKGROUND),
+        hex(FOREGROUND),
+        svg
+    )
+}
+
+// the inclusive range of values held by the bucket
+fn range(bucket: &Bucket, unit: Unit) -> String {
+    let low = bucket.value() - bucket.width().saturating_sub(1).min(bucket.value());
+    let suffix = match unit {
+        Unit::Nanoseconds => " ns",
+        Unit::Count => "",
+    };
+    if low == bucket.value() {
+        format!("{}{}", low, suffix)
+    } else {
+        format!("{} to {}{}", low, bucket.value(), suffix)
+    }
+}
+
+fn hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use histogram::Histogram;
+    use render::tests::heatmap;
+
+    #[test]
+    fn svg_tooltips() {
+        let heatmap = heatmap();
+        let svg = svg(&heatmap, &WaterfallOptions::default(), Unit::Nanoseconds);
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        // three non-empty buckets in each of 60 slices
+        assert_eq!(svg.matches("<title>").count(), 180);
+        assert!(svg.contains("<title>+0s\n"));
+        assert!(svg.contains("<title>+59s\n"));
+        assert!(svg.contains(" ns\ncount 100</title>"));
+        // the slices span one minute, so ticks every 30s are at +0s and +30s
+        assert!(svg.contains(">0s</text>"));
+        let ticks = WaterfallOptions::default().tick_interval(30);
+        assert!(super::svg(&heatmap, &ticks, Unit::Nanoseconds).contains(">30s</text>"));
+
+        let html = html(&svg, "ok <1>");
+        assert!(html.contains("<title>ok &lt;1&gt;</title>"));
+        assert!(html.contains(&svg));
+    }
+
+    #[test]
+    fn ranges() {
+        let mut histogram = Histogram::new();
+        histogram.increment(5).unwrap();
+        let bucket = (&histogram).into_iter().find(|b| b.count() > 0).unwrap();
+        assert_eq!(range(&bucket, Unit::Count), "5");
+    }
+}
@@ -0,0 +1,378 @@
+// render heatmaps as PNG waterfalls, the value axis is labelled on the left
+// and the time axis along the bottom
+
+use common::SECOND;
+use data::horizon_label;
+use heatmap::Heatmap;
+use png::{BitDepth, ColorType, Encoder};
+use std::io;
+use super::font::{glyph, GLYPH_ADVANCE, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::{columns, format_count, format_nanoseconds, max_count, Column, Unit,
+            WaterfallOptions, FOREGROUND, OVERLAYS};
+
+// room for five glyphs of value labels, and the time ticks and their labels
+const LEFT_MARGIN: usize = 24;
+const BOTTOM_MARGIN: usize = 8;
+
+/// an RGB image
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Image {
+        Image {
+            width: width,
+            height: height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let i = (y * self.width + x) * 3;
+            self.pixels[i..(i + 3)].copy_from_slice(&color);
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, text: &str, color: [u8; 3]) {
+        for (n, c) in text.chars().enumerate() {
+            let rows = glyph(c);
+            for (dy, row) in rows.iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    if row & (0b100 >> dx) != 0 {
+                        self.set(x + n * GLYPH_ADVANCE + dx, y + dy, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// encode the image as a PNG
+    pub fn png(&self) -> Result<Vec<u8>, io::Error> {
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output, self.width as u32, self.height as u32);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.pixels)?;
+            writer.finish()?;
+        }
+        Ok(output)
+    }
+}
+
+/// render the heatmap as a waterfall, the newest slice on the right and the
+/// largest values at the top
+pub fn render(heatmap: &Heatmap, options: &WaterfallOptions, unit: Unit) -> Image {
+    let max_value = options.max_value.unwrap_or(u64::MAX);
+    let percentiles: Vec<f64> = options
+        .percentiles
+        .iter()
+        .map(|p| f64::from(*p) / 10_000.0)
+        .collect();
+    let columns = columns(heatmap, options, &percentiles);
+
+    // every slice has the same buckets, so the rows are taken from the first
+    let values: Vec<u64> = match columns.first() {
+        Some(column) => column.buckets.iter().map(|b| b.value()).collect(),
+        None => Vec::new(),
+    };
+    let max_count = max_count(&columns);
+
+    let plot_width = columns.len().max(1);
+    let plot_height = values.len().max(1);
+    let (left, bottom) = if options.labels {
+        (LEFT_MARGIN, BOTTOM_MARGIN)
+    } else {
+        (0, 0)
+    };
+    let mut image = Image::new(left + plot_width, plot_height + bottom);
+
+    for (x, column) in columns.iter().enumerate() {
+        for bucket in &column.buckets {
+            if let Ok(row) = values.binary_search(&bucket.value()) {
+                let color = options.color(options.intensity(bucket.count(), max_count));
+                image.set(left + x, plot_height - 1 - row, color);
+            }
+        }
+        for (i, value) in column.percentiles.iter().enumerate() {
+            let value = match *value {
+                Some(v) if v >= options.min_value && v <= max_value => v,
+                _ => continue,
+            };
+            // the row of the first bucket which holds the value
+            let row = match values.binary_search(&value) {
+                Ok(row) | Err(row) => row.min(plot_height - 1),
+            };
+            image.set(left + x, plot_height - 1 - row, OVERLAYS[i % OVERLAYS.len()]);
+        }
+    }
+
+    if options.labels {
+        draw_value_axis(&mut image, &values, plot_height, unit);
+        draw_time_axis(&mut image, &columns, plot_height, options.tick_interval);
+        for (i, p) in percentiles.iter().enumerate() {
+            let legend = format!("p{}", p);
+            let x = left + 2 + i * (legend.len() + 1) * GLYPH_ADVANCE;
+            image.text(x, 1, &legend, OVERLAYS[i % OVERLAYS.len()]);
+        }
+    }
+    image
+}
+
+// label a row every few glyph heights, starting from the smallest value
+fn draw_value_axis(image: &mut Image, values: &[u64], plot_height: usize, unit: Unit) {
+    let spacing = GLYPH_HEIGHT * 3;
+    let mut row = 0;
+    while row < values.len() {
+        let y = plot_height - 1 - row;
+        image.set(LEFT_MARGIN - 2, y, FOREGROUND);
+        image.set(LEFT_MARGIN - 1, y, FOREGROUND);
+        let label = match unit {
+            Unit::Nanoseconds => format_nanoseconds(values[row]),
+            Unit::Count => format_count(values[row]),
+        };
+        let y = (y + 1).saturating_sub(GLYPH_HEIGHT);
+        image.text(0, y, &label, FOREGROUND);
+        row += spacing;
+    }
+}
+
+// tick the slices which start on a multiple of the interval from the first,
+// skipping labels which would overlap the previous one
+fn draw_time_axis(image: &mut Image, columns: &[Column], plot_height: usize, interval: u64) {
+    let first = match columns.first() {
+        Some(column) => column.start,
+        None => return,
+    };
+    let mut free = 0;
+    for (x, column) in columns.iter().enumerate() {
+        let seconds = column.start.saturating_sub(first) / SECOND;
+        if interval == 0 || seconds % interval != 0 {
+            continue;
+        }
+        let x = LEFT_MARGIN + x;
+        image.set(x, plot_height, FOREGROUND);
+        image.set(x, plot_height + 1, FOREGROUND);
+        let label = horizon_label(seconds as usize);
+        if x >= free {
+            image.text(x, plot_height + 2, &label, FOREGROUND);
+            free = x + (label.len() + 1) * GLYPH_ADVANCE;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use png::Decoder;
+    use render::tests::{heatmap, values};
+    use render::{Palette, Scale, BACKGROUND};
+
+    // sixteen one second slices, with two samples of 1 in the first, one of 2
+    // in the second and none in the others
+    fn small() -> Heatmap {
+        let mut heatmap = Heatmap::configure()
+            .slice_duration(SECOND)
+            .num_slices(16)
+            .precision(1)
+            .max_value(10)
+            .start(0)
+            .build()
+            .unwrap();
+        heatmap.increment_by(0, 1, 2).unwrap();
+        heatmap.increment_by(SECOND, 2, 1).unwrap();
+        heatmap
+    }
+
+    // the glyph drawn in the color with its top left corner at x, y
+    fn read(
+        pixels: &[[u8; 3]],
+        width: usize,
+        x: usize,
+        y: usize,
+        color: [u8; 3],
+    ) -> [u8; GLYPH_HEIGHT] {
+        let mut rows = [0; GLYPH_HEIGHT];
+        for (dy, row) in rows.iter_mut().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                if pixels[(y + dy) * width + x + dx] == color {
+                    *row |= 0b100 >> dx;
+                }
+            }
+        }
+        rows
+    }
+
+    // decode a PNG into its width, height and RGB pixels
+    fn decode(png: &[u8]) -> (usize, usize, Vec<[u8; 3]>) {
+        let mut reader = Decoder::new(png).read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        assert_eq!(info.color_type, ColorType::Rgb);
+        assert_eq!(info.bit_depth, BitDepth::Eight);
+        let pixels = buffer[..info.buffer_size()]
+            .chunks(3)
+            .map(|p| [p[0], p[1], p[2]])
+            .collect();
+        (info.width as usize, info.height as usize, pixels)
+    }
+
+    #[test]
+    fn dimensions_and_labels() {
+        let heatmap = heatmap();
+        let rows = values(&heatmap).len();
+
+        let options = WaterfallOptions::default().labels(false);
+        let png = render(&heatmap, &options, Unit::Count).png().unwrap();
+        let (width, height, pixels) = decode(&png);
+        assert_eq!((width, height), (60, rows));
+        assert!(!pixels.contains(&FOREGROUND));
+
+        let image = render(&heatmap, &WaterfallOptions::default(), Unit::Nanoseconds);
+        let (width, height, pixels) = decode(&image.png().unwrap());
+        assert_eq!((width, height), (60 + LEFT_MARGIN, rows + BOTTOM_MARGIN));
+        // the value labels are drawn in the left margin
+        let labelled = pixels
+            .iter()
+            .enumerate()
+            .filter(|&(i, p)| i % width < LEFT_MARGIN && *p == FOREGROUND)
+            .count();
+        assert!(labelled > 0);
+    }
+
+    #[test]
+    fn clipping_and_overlays() {
+        let heatmap = heatmap();
+        let options = WaterfallOptions::default()
+            .labels(false)
+            .scale(Scale::Log)
+            .palette(Palette::Grayscale)
+            .clip(500, 20_000_000)
+            .percentile(50.0)
+            .percentile(99.0);
+        let png = render(&heatmap, &options, Unit::Nanoseconds).png().unwrap();
+        let (width, height, pixels) = decode(&png);
+        let rows = values(&heatmap)
+            .iter()
+            .filter(|v| **v >= 500 && **v <= 20_000_000)
+            .count();
+        assert_eq!((width, height), (60, rows));
+
+        // the p99 of each slice is the largest value, which lies within the
+        // clipped range, so each column has one pixel in the second color
+        for x in 0..width {
+            let overlay = (0..height)
+                .filter(|y| pixels[y * width + x] == OVERLAYS[1])
+                .count();
+            assert_eq!(overlay, 1);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let heatmap = Heatmap::configure()
+            .slice_duration(SECOND)
+            .num_slices(0)
+            .build()
+            .unwrap();
+        let options = WaterfallOptions::default().labels(false);
+        let png = render(&heatmap, &options, Unit::Count).png().unwrap();
+        assert_eq!(decode(&png), (1, 1, vec![BACKGROUND]));
+    }
+
+    #[test]
+    fn bucket_pixels() {
+        let heatmap = small();
+        let rows = values(&heatmap);
+        let options = WaterfallOptions::default().labels(false);
+        let png = render(&heatmap, &options, Unit::Count).png().unwrap();
+        let (width, height, pixels) = decode(&png);
+        assert_eq!((width, height), (16, rows.len()));
+        // the largest values are at the top
+        let y = |value| height - 1 - rows.binary_search(&value).unwrap();
+
+        // the busiest bucket is the top of the palette and one with half of
+        // its count is the middle, all other pixels are the background
+        let mut expected = vec![BACKGROUND; width * height];
+        expected[y(1) * width] = [255, 0, 0];
+        expected[y(2) * width + 1] = [0, 255, 0];
+        assert_eq!(pixels, expected);
+
+        // ln(2) / ln(3) of the way from dark gray to white
+        let options = options.scale(Scale::Log).palette(Palette::Grayscale);
+        let png = render(&heatmap, &options, Unit::Count).png().unwrap();
+        expected[y(1) * width] = [255, 255, 255];
+        expected[y(2) * width + 1] = [173, 173, 173];
+        assert_eq!(decode(&png).2, expected);
+    }
+
+    #[test]
+    fn overlay_pixels() {
+        let heatmap = small();
+        let rows = values(&heatmap);
+        let options = WaterfallOptions::default()
+            .labels(false)
+            .percentile(100.0);
+        let png = render(&heatmap, &options, Unit::Count).png().unwrap();
+        let (width, height, pixels) = decode(&png);
+        let y = |value| height - 1 - rows.binary_search(&value).unwrap();
+
+        // the overlay replaces the bucket holding the percentile, and empty
+        // slices have none
+        let mut expected = vec![BACKGROUND; width * height];
+        expected[y(1) * width] = OVERLAYS[0];
+        expected[y(2) * width + 1] = OVERLAYS[0];
+        assert_eq!(pixels, expected);
+
+        // a percentile outside of the clipped range is not drawn
+        let options = options.clip(2, 10);
+        let png = render(&heatmap, &options, Unit::Count).png().unwrap();
+        let (width, height, pixels) = decode(&png);
+        assert_eq!(height, rows.len() - 2);
+        let mut expected = vec![BACKGROUND; width * height];
+        expected[(height - 1) * width + 1] = OVERLAYS[0];
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn label_pixels() {
+        let heatmap = small();
+        let rows = values(&heatmap);
+        let options = WaterfallOptions::default().percentile(50.0);
+        let png = render(&heatmap, &options, Unit::Count).png().unwrap();
+        let (width, height, pixels) = decode(&png);
+        let plot_height = rows.len();
+        assert_eq!((width, height), (LEFT_MARGIN + 16, plot_height + BOTTOM_MARGIN));
+        let at = |x: usize, y: usize| pixels[y * width + x];
+
+        // the smallest value is labelled next to its tick on the bottom row
+        assert_eq!(at(LEFT_MARGIN - 2, plot_height - 1), FOREGROUND);
+        assert_eq!(at(LEFT_MARGIN - 1, plot_height - 1), FOREGROUND);
+        let label = format_count(rows[0]);
+        for (n, c) in label.chars().enumerate() {
+            let x = n * GLYPH_ADVANCE;
+            let y = plot_height - GLYPH_HEIGHT;
+            assert_eq!(read(&pixels, width, x, y, FOREGROUND), glyph(c));
+        }
+
+        // the first slice is ticked and labelled below the plot
+        assert_eq!(at(LEFT_MARGIN, plot_height), FOREGROUND);
+        assert_eq!(at(LEFT_MARGIN, plot_height + 1), FOREGROUND);
+        assert_eq!(at(LEFT_MARGIN + 1, plot_height), BACKGROUND);
+        let y = plot_height + 2;
+        assert_eq!(read(&pixels, width, LEFT_MARGIN, y, FOREGROUND), glyph('0'));
+        let x = LEFT_MARGIN + GLYPH_ADVANCE;
+        assert_eq!(read(&pixels, width, x, y, FOREGROUND), glyph('s'));
+
+        // the legend is drawn in the color of its overlay
+        for (n, c) in "p50".chars().enumerate() {
+            let x = LEFT_MARGIN + 2 + n * GLYPH_ADVANCE;
+            assert_eq!(read(&pixels, width, x, 1, OVERLAYS[0]), glyph(c));
+        }
+    }
+}
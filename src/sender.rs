@@ -92,7 +92,7 @@ impl<T: Hash + Eq + Send + Clone> Sender<T> {
 
     #[inline]
     /// mock try_send `Sample` to the `Receiver`
-    pub fn try_send(&mut self, sample: Sample<T>) -> Result<(), (Sample<T>)> {
+    pub fn try_send(&mut self, sample: Sample<T>) -> Result<(), Sample<T>> {
         let mut buffer = self.buffer.take().unwrap();
         if buffer.len() < self.batch_size - 1 {
             buffer.push(sample);
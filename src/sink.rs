@@ -0,0 +1,174 @@
+// `Sink`s receive the output of the `Receiver` as each window and run ends
+
+use artifacts::ArtifactError;
+use data::{Heatmaps, Histograms, Meters};
+use heatmap::Heatmap;
+use histogram::Histogram;
+use std::hash::Hash;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// a `Window` describes a reporting interval which has just closed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Window {
+    /// the number of windows which closed before this one
+    pub index: usize,
+    /// the start of the window in nanoseconds since the unix epoch
+    pub start: u64,
+    /// the end of the window in nanoseconds since the unix epoch
+    pub end: u64,
+    /// the start of the window as a `Clocksource` counter value
+    pub start_counter: u64,
+    /// the end of the window as a `Clocksource` counter value
+    pub end_counter: u64,
+    /// the length of the window in nanoseconds
+    pub duration: u64,
+    /// the total number of `Sample`s processed by the end of the window
+    pub samples: u64,
+    /// the total number of batches of `Sample`s processed by the end of the
+    /// window
+    pub batches: u64,
+    /// the total number of labelled `Sample`s whose labels were not recorded
+    /// by the end of the window, as their metric had reached
+    /// `Config::label_cardinality`
+    pub labels_dropped: u64,
+    /// the total number of `Sample`s not recorded by the end of the window
+    /// for a metric which `AllCount` or `AllLatencyPercentile` interests could
+    /// not register, as `Config::auto_channels` metrics were registered
+    pub auto_rejected: u64,
+}
+
+/// `WindowHistograms` give access to the histograms of a window which has
+/// just closed, before they are cleared for the next window
+pub struct WindowHistograms<'a, T: 'a> {
+    latency_histograms: &'a Histograms<T>,
+    value_histograms: &'a Histograms<T>,
+}
+
+impl<'a, T: Hash + Eq> WindowHistograms<'a, T> {
+    pub(crate) fn new(
+        latency_histograms: &'a Histograms<T>,
+        value_histograms: &'a Histograms<T>,
+    ) -> WindowHistograms<'a, T> {
+        WindowHistograms {
+            latency_histograms: latency_histograms,
+            value_histograms: value_histograms,
+        }
+    }
+
+    /// the latency `Histogram` for the metric, if it has a
+    /// `LatencyPercentile` interest registered
+    pub fn latency_histogram(&self, key: &T) -> Option<&Histogram> {
+        self.latency_histograms.data.get(key)
+    }
+
+    /// the value `Histogram` for the metric, if it has a `ValuePercentile`
+    /// interest registered
+    pub fn value_histogram(&self, key: &T) -> Option<&Histogram> {
+        self.value_histograms.data.get(key)
+    }
+
+    /// all the latency `Histogram`s, keyed by metric
+    pub fn latency_histograms(&self) -> Vec<(&T, &Histogram)> {
+        self.latency_histograms.data.iter().collect()
+    }
+
+    /// all the value `Histogram`s, keyed by metric
+    pub fn value_histograms(&self) -> Vec<(&T, &Histogram)> {
+        self.value_histograms.data.iter().collect()
+    }
+}
+
+/// a `Run` gives access to the heatmaps at the end of each full run, which
+/// spans `Config::windows` windows
+pub struct Run<'a, T: 'a> {
+    index: usize,
+    latency_heatmaps: &'a Heatmaps<T>,
+    value_heatmaps: &'a Heatmaps<T>,
+    artifact_errors: &'a [ArtifactError],
+}
+
+impl<'a, T: Hash + Eq> Run<'a, T> {
+    pub(crate) fn new(
+        index: usize,
+        latency_heatmaps: &'a Heatmaps<T>,
+        value_heatmaps: &'a Heatmaps<T>,
+        artifact_errors: &'a [ArtifactError],
+    ) -> Run<'a, T> {
+        Run {
+            index: index,
+            latency_heatmaps: latency_heatmaps,
+            value_heatmaps: value_heatmaps,
+            artifact_errors: artifact_errors,
+        }
+    }
+
+    /// the number of runs which completed before this one
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// the artifacts which failed to save at the end of the run
+    pub fn artifact_errors(&self) -> &[ArtifactError] {
+        self.artifact_errors
+    }
+
+    /// the latency `Heatmap` for the metric, if it has a trace or waterfall
+    /// interest registered
+    pub fn latency_heatmap(&self, key: &T) -> Option<&Heatmap> {
+        self.latency_heatmaps.data.get(key)
+    }
+
+    /// the value `Heatmap` for the metric, if it has a trace or waterfall
+    /// interest registered
+    pub fn value_heatmap(&self, key: &T) -> Option<&Heatmap> {
+        self.value_heatmaps.data.get(key)
+    }
+
+    /// all the latency `Heatmap`s, keyed by metric
+    pub fn latency_heatmaps(&self) -> Vec<(&T, &Heatmap)> {
+        self.latency_heatmaps.data.iter().collect()
+    }
+
+    /// all the value `Heatmap`s, keyed by metric
+    pub fn value_heatmaps(&self) -> Vec<(&T, &Heatmap)> {
+        self.value_heatmaps.data.iter().collect()
+    }
+}
+
+/// a `Sink` is called by the `Receiver` at the close of each window and the
+/// end of each run, register them with `Config::sink`
+///
+/// errors returned by a `Sink` are logged by the `Receiver`
+///
+/// sinks are called in turn on the thread of the `Receiver`, which processes
+/// no `Sample`s until they return. a sink which blocks, such as one waiting
+/// on the timeout of a network connection, delays the next window and leaves
+/// the `Sender`s to fill the queue, so slow exports should be handed to
+/// another thread
+pub trait Sink<T>: Send {
+    /// called with the freshly calculated `Meters` as each window closes
+    fn window(&mut self, window: &Window, meters: &Meters<T>) -> Result<(), io::Error>;
+
+    /// called with the histograms of each window as it closes, after
+    /// `Sink::window`
+    fn histograms(
+        &mut self,
+        window: &Window,
+        histograms: &WindowHistograms<T>,
+    ) -> Result<(), io::Error> {
+        let _ = (window, histograms);
+        Ok(())
+    }
+
+    /// called after each run of `Config::windows` windows, before the
+    /// heatmaps are cleared in service mode
+    fn run(&mut self, run: &Run<T>) -> Result<(), io::Error> {
+        let _ = run;
+        Ok(())
+    }
+}
+
+/// builds the `Sink` of each `Receiver` built from a `Config`, so receivers
+/// never share the state of a sink
+pub type SinkFactory<T> = Arc<Mutex<Box<dyn FnMut() -> Box<dyn Sink<T>> + Send>>>;
@@ -0,0 +1,279 @@
+// `Statsd` pushes `Meters` to a StatsD or DogStatsD agent over UDP
+
+use data::{MeterKind, Meters, Reading};
+use fnv::FnvHashMap;
+use sink::{Sink, Window};
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+
+/// a `Statsd` exporter is a `Sink` which emits the `Meters` as StatsD
+/// packets at the end of each window
+///
+/// counts are emitted as StatsD counters of the change since the previous
+/// window, all other meters are emitted as gauges
+///
+/// the `:`, `|`, `,` and `@` characters, which delimit the fields of a StatsD
+/// line, are replaced with `_` in metric names and tags
+#[derive(Clone)]
+pub struct Statsd {
+    address: SocketAddr,
+    prefix: String,
+    max_packet_size: usize,
+    dogstatsd: bool,
+    socket: Option<Arc<UdpSocket>>,
+    counts: FnvHashMap<String, u64>,
+}
+
+impl Statsd {
+    /// create a new `Statsd` exporter which sends to the given address
+    ///
+    /// # Example
+    /// ```
+    /// # use tic::Statsd;
+    /// let statsd = Statsd::new("127.0.0.1:8125").prefix("tic");
+    /// ```
+    pub fn new<U: ToSocketAddrs>(address: U) -> Statsd {
+        let address = address
+            .to_socket_addrs()
+            .expect("SocketAddr lookup failed")
+            .next()
+            .expect("SocketAddr resolved to empty set");
+
+        Statsd {
+            address: address,
+            prefix: "".to_owned(),
+            max_packet_size: 1432,
+            dogstatsd: false,
+            socket: None,
+            counts: FnvHashMap::default(),
+        }
+    }
+
+    /// set the prefix prepended to each metric name: default none
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// set the max size of a single UDP packet in bytes: default 1432
+    ///
+    /// metrics are batched into newline separated packets up to this size
+    pub fn max_packet_size(mut self, bytes: usize) -> Self {
+        self.max_packet_size = bytes;
+        self
+    }
+
+    /// enable DogStatsD tags: default false
+    ///
+    /// when enabled, the metric label is sent as a `metric:` tag and the
+    /// metric name contains only the statistic, eg: `p99_nanoseconds`
+    pub fn dogstatsd(mut self, enabled: bool) -> Self {
+        self.dogstatsd = enabled;
+        self
+    }
+
+    /// send the `Meters` to the StatsD agent
+    pub fn emit<T>(&mut self, meters: &Meters<T>) -> Result<(), io::Error>
+    where
+        T: Hash + Eq + Send + Display + Clone,
+    {
+        let mut lines = Vec::new();
+        let mut counted = HashSet::new();
+        for meter in meters.iter() {
+            let (value, kind) = match (meter.kind, meter.reading) {
+                (&MeterKind::Count, Reading::Integer(count)) => {
+                    let name = meter.name();
+                    let previous = self.counts.insert(name.clone(), count).unwrap_or(0);
+                    counted.insert(name);
+                    let delta = if count >= previous {
+                        count - previous
+                    } else {
+                        count
+                    };
+                    (format!("{}", delta), "c")
+                }
+                (_, reading) => (format!("{}", reading), "g"),
+            };
+            let line = if self.dogstatsd {
+                let tags = format!("|#metric:{}", sanitize(&meter.channel.to_string()));
+                self.line(&meter.kind.to_string(), &value, kind, &tags)
+            } else {
+                self.line(&meter.name(), &value, kind, "")
+            };
+            lines.push(line);
+        }
+        // forget the counts of metrics which are no longer reported
+        self.counts.retain(|name, _| counted.contains(name));
+        // totals of the receiver itself are sent as gauges, without tags
+        for (name, value) in meters.receiver_meters() {
+            let line = self.line(name, &format!("{}", value), "g", "");
+            lines.push(line);
+        }
+        self.send(&lines)
+    }
+
+    // format a single StatsD line
+    fn line(&self, name: &str, value: &str, kind: &str, tags: &str) -> String {
+        let name = sanitize(name);
+        if self.prefix.is_empty() {
+            format!("{}:{}|{}{}", name, value, kind, tags)
+        } else {
+            format!("{}.{}:{}|{}{}", self.prefix, name, value, kind, tags)
+        }
+    }
+
+    // batch the lines into packets and send them
+    fn send(&mut self, lines: &[String]) -> Result<(), io::Error> {
+        if self.socket.is_none() {
+            let local = if self.address.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            self.socket = Some(Arc::new(UdpSocket::bind(local)?));
+        }
+        let socket = self.socket.clone().unwrap();
+
+        let mut packet = String::new();
+        for line in lines {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > self.max_packet_size {
+                socket.send_to(packet.as_bytes(), self.address)?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(line);
+        }
+        if !packet.is_empty() {
+            socket.send_to(packet.as_bytes(), self.address)?;
+        }
+        Ok(())
+    }
+}
+
+// replace the characters which delimit the fields of a StatsD line
+fn sanitize(name: &str) -> String {
+    name.replace(|c| c == ':' || c == '|' || c == ',' || c == '@', "_")
+}
+
+impl<T: Hash + Eq + Send + Display + Clone> Sink<T> for Statsd {
+    fn window(&mut self, _: &Window, meters: &Meters<T>) -> Result<(), io::Error> {
+        self.emit(meters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Percentile;
+    use std::time::Duration;
+
+    fn listen() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        socket
+    }
+
+    fn receive(socket: &UdpSocket) -> String {
+        let mut buffer = [0; 65536];
+        let (len, _) = socket.recv_from(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..len]).into_owned()
+    }
+
+    #[test]
+    fn counters_and_gauges() {
+        let socket = listen();
+        let mut statsd = Statsd::new(socket.local_addr().unwrap()).prefix("tic");
+
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 10);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "tic.ok_count:10|c");
+
+        meters.set_count("ok".to_owned(), 25);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "tic.ok_count:15|c");
+
+        meters.clear();
+        meters.set_latency_percentile("ok".to_owned(), Percentile("p99".to_owned(), 99.0), 42);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "tic.ok_p99_nanoseconds:42|g");
+    }
+
+    #[test]
+    fn receiver_meters() {
+        let socket = listen();
+        let mut statsd = Statsd::new(socket.local_addr().unwrap()).dogstatsd(true);
+
+        let mut meters = Meters::<String>::new();
+        meters.set_receiver_meter("tic_artifact_errors", 2);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "tic_artifact_errors:2|g");
+    }
+
+    #[test]
+    fn dogstatsd_tags() {
+        let socket = listen();
+        let mut statsd = Statsd::new(socket.local_addr().unwrap()).dogstatsd(true);
+
+        let mut meters = Meters::<String>::new();
+        meters.set_value("ok".to_owned(), 7);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "value:7|g|#metric:ok");
+    }
+
+    #[test]
+    fn pruned_counts() {
+        let socket = listen();
+        let mut statsd = Statsd::new(socket.local_addr().unwrap());
+
+        let mut meters = Meters::<String>::new();
+        meters.set_count("ok".to_owned(), 10);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "ok_count:10|c");
+        assert_eq!(statsd.counts.len(), 1);
+
+        meters.clear();
+        meters.set_value("ok".to_owned(), 1);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "ok_value:1|g");
+        assert!(statsd.counts.is_empty());
+    }
+
+    #[test]
+    fn sanitized() {
+        let socket = listen();
+        let mut statsd = Statsd::new(socket.local_addr().unwrap());
+
+        let mut meters = Meters::<String>::new();
+        meters.set_value("a:b|c,d@e".to_owned(), 1);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "a_b_c_d_e_value:1|g");
+
+        let mut statsd = statsd.dogstatsd(true);
+        statsd.emit(&meters).unwrap();
+        assert_eq!(receive(&socket), "value:1|g|#metric:a_b_c_d_e");
+    }
+
+    #[test]
+    fn packet_size() {
+        let socket = listen();
+        let mut statsd = Statsd::new(socket.local_addr().unwrap()).max_packet_size(20);
+
+        let mut meters = Meters::<String>::new();
+        meters.set_value("a".to_owned(), 1);
+        meters.set_value("b".to_owned(), 2);
+        statsd.emit(&meters).unwrap();
+
+        let mut lines = vec![receive(&socket), receive(&socket)];
+        lines.sort();
+        assert_eq!(lines, vec!["a_value:1|g", "b_value:2|g"]);
+    }
+}
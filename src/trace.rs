@@ -0,0 +1,461 @@
+// `Trace` loads a saved heatmap trace so it can be cropped and re-rendered
+// after the run
+
+use common::SECOND;
+use heatmap::Heatmap;
+use histogram::Histogram;
+use render::{self, Unit, WaterfallOptions};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+// the precision and max value of the histograms of a heatmap, which a trace
+// records so it is loaded into the same buckets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub precision: u32,
+    pub max_value: u64,
+}
+
+impl Layout {
+    // the heatmap keeps its config private, so the layout is recovered by
+    // matching the buckets of a slice against those of each precision
+    pub fn of(heatmap: &Heatmap) -> Option<Layout> {
+        let slice = heatmap.into_iter().next()?;
+        let histogram = slice.histogram();
+        let values: Vec<u64> = histogram.into_iter().map(|b| b.value()).collect();
+        let max_value = *values.last()?;
+        // a precision has at least 10^precision buckets, so larger ones are
+        // not built
+        let mut buckets = 1;
+        for precision in 1..10 {
+            if buckets > values.len() {
+                break;
+            }
+            buckets *= 10;
+            let candidate = Histogram::configure()
+                .precision(precision)
+                .max_value(max_value)
+                .build();
+            if let Some(candidate) = candidate {
+                if candidate.into_iter().map(|b| b.value()).eq(values.iter().cloned()) {
+                    return Some(Layout {
+                        precision: precision,
+                        max_value: max_value,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// write the heatmap as a trace: a header of the precision, memory limit,
+/// max value, slice duration, number of slices and start time, then the
+/// start time, value and count of each non-empty bucket of each slice
+pub fn write<W: Write>(heatmap: &Heatmap, writer: &mut W) -> Result<(), io::Error> {
+    let layout = match Layout::of(heatmap) {
+        Some(layout) => layout,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown layout of heatmap",
+            ))
+        }
+    };
+    let (start, duration) = match heatmap.into_iter().next() {
+        Some(slice) => (slice.start(), slice.stop() - slice.start()),
+        None => (0, SECOND),
+    };
+    writeln!(
+        writer,
+        "{} 0 {} {} {} {}",
+        layout.precision,
+        layout.max_value,
+        duration,
+        heatmap.num_slices(),
+        start
+    )?;
+    for slice in heatmap {
+        let start = slice.start();
+        let histogram = slice.histogram();
+        for bucket in &histogram {
+            if bucket.count() > 0 {
+                writeln!(writer, "{} {} {}", start, bucket.value(), bucket.count())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+/// read a trace as written by `write`, returning the heatmap, the layout of
+/// its histograms and its slice duration. a trace which is empty, truncated
+/// or malformed is an `InvalidData` error
+pub fn read<R: BufRead>(reader: R) -> Result<(Heatmap, Layout, u64), io::Error> {
+    let mut lines = reader.lines();
+    let header = match lines.next() {
+        Some(header) => header?,
+        None => return Err(invalid(1, "empty trace")),
+    };
+    let fields = parse_line(&header, 1, 6)?;
+    let layout = Layout {
+        precision: narrow(fields[0], 1)?,
+        max_value: fields[2],
+    };
+    let duration = fields[3];
+    // the heatmap divides its memory limit between the slices as a u32
+    let slices: u32 = narrow(fields[4], 1)?;
+    // the histograms of a heatmap can't be built for these
+    if layout.precision == 0 || layout.precision > 9 || duration == 0 || slices == 0 {
+        return Err(invalid(1, "unsupported heatmap config"));
+    }
+    let mut heatmap = Heatmap::configure()
+        .precision(layout.precision)
+        .max_memory(narrow(fields[1], 1)?)
+        .max_value(layout.max_value)
+        .slice_duration(duration)
+        .num_slices(slices as usize)
+        .start(fields[5])
+        .build()
+        .ok_or_else(|| invalid(1, "heatmap exceeds its memory limit"))?;
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        let fields = parse_line(&line, i + 2, 3)?;
+        if heatmap.increment_by(fields[0], fields[1], fields[2]).is_err() {
+            return Err(invalid(i + 2, "sample is outside of the heatmap"));
+        }
+    }
+    Ok((heatmap, layout, duration))
+}
+
+// parse the given number of whitespace separated integers from a line
+fn parse_line(line: &str, number: usize, count: usize) -> Result<Vec<u64>, io::Error> {
+    let fields: Result<Vec<u64>, _> = line.split_whitespace().map(|f| f.parse()).collect();
+    match fields {
+        Ok(fields) => {
+            if fields.len() == count {
+                Ok(fields)
+            } else {
+                Err(invalid(number, "malformed line"))
+            }
+        }
+        Err(_) => Err(invalid(number, "malformed line")),
+    }
+}
+
+// convert a field to a narrower integer type
+fn narrow<T: TryFrom<u64>>(value: u64, number: usize) -> Result<T, io::Error> {
+    T::try_from(value).map_err(|_| invalid(number, "value out of range"))
+}
+
+fn invalid(number: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid trace at line {}: {}", number, message),
+    )
+}
+
+/// a `Trace` is a latency heatmap loaded from a trace file, as saved for a
+/// `LatencyTrace` interest or `Config::trace_file`
+///
+/// # Example
+/// ```no_run
+/// # use tic::{Trace, WaterfallOptions};
+/// let trace = Trace::load("ok_trace.txt")
+///     .unwrap()
+///     .crop_time(60, 120)
+///     .crop_values(0, 1_000_000);
+/// let png = trace.waterfall(&WaterfallOptions::default().percentile(99.0)).unwrap();
+/// for (start, percentiles) in trace.percentiles(&[50.0, 99.0]) {
+///     println!("{} {:?}", start, percentiles);
+/// }
+/// ```
+pub struct Trace {
+    heatmap: Heatmap,
+    layout: Layout,
+    duration: u64,
+}
+
+impl Trace {
+    /// load the trace file at the given path. a file which is not a trace is
+    /// an `InvalidData` error
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Trace, io::Error> {
+        let (heatmap, layout, duration) = read(BufReader::new(File::open(path)?))?;
+        Ok(Trace {
+            heatmap: heatmap,
+            layout: layout,
+            duration: duration,
+        })
+    }
+
+    /// the number of slices in the trace, each covering one second when saved
+    /// by a `Receiver`
+    pub fn slices(&self) -> usize {
+        self.heatmap.num_slices() as usize
+    }
+
+    /// keep the slices from `start` until `end` seconds after the start of
+    /// the trace
+    pub fn crop_time(self, start: u64, end: u64) -> Self {
+        let first = (&self.heatmap).into_iter().next().map(|s| s.start());
+        let first = match first {
+            Some(first) => first,
+            None => return self,
+        };
+        let start = first.saturating_add(start.saturating_mul(SECOND));
+        let end = first.saturating_add(end.saturating_mul(SECOND));
+        self.rebuild(|time, _| time >= start && time < end)
+    }
+
+    /// keep the samples with values from `min` to `max` inclusive
+    pub fn crop_values(self, min: u64, max: u64) -> Self {
+        self.rebuild(|_, value| value >= min && value <= max)
+    }
+
+    // build a new heatmap with the config of the trace from the samples of
+    // the slices and buckets which match the filter, covering only the
+    // remaining slices
+    fn rebuild<F: Fn(u64, u64) -> bool>(self, keep: F) -> Self {
+        let mut samples = Vec::new();
+        let mut starts = Vec::new();
+        for slice in &self.heatmap {
+            let start = slice.start();
+            let mut kept = false;
+            let histogram = slice.histogram();
+            for bucket in &histogram {
+                if keep(start, bucket.value()) {
+                    kept = true;
+                    if bucket.count() > 0 {
+                        samples.push((start, bucket.value(), bucket.count()));
+                    }
+                }
+            }
+            if kept {
+                starts.push(start);
+            }
+        }
+        let first = match starts.first() {
+            Some(first) => *first,
+            None => return self,
+        };
+        let mut heatmap = Heatmap::configure()
+            .slice_duration(self.duration)
+            .num_slices(starts.len())
+            .precision(self.layout.precision)
+            .max_value(self.layout.max_value)
+            .start(first)
+            .build()
+            .unwrap();
+        for (time, value, count) in samples {
+            let _ = heatmap.increment_by(time, value, count);
+        }
+        Trace {
+            heatmap: heatmap,
+            layout: self.layout,
+            duration: self.duration,
+        }
+    }
+
+    /// render the trace as a PNG waterfall
+    pub fn waterfall(&self, options: &WaterfallOptions) -> Result<Vec<u8>, io::Error> {
+        render::render(&self.heatmap, options, Unit::Nanoseconds).png()
+    }
+
+    /// render the trace as an SVG with a tooltip for each cell, see
+    /// `Interest::LatencySvg`
+    pub fn svg(&self, options: &WaterfallOptions) -> String {
+        render::svg(&self.heatmap, options, Unit::Nanoseconds)
+    }
+
+    /// render the trace as an SVG in a self-contained HTML page with the given
+    /// title
+    pub fn html(&self, options: &WaterfallOptions, title: &str) -> String {
+        render::html(&self.svg(options), title)
+    }
+
+    /// the start time of each slice and the given percentiles of its samples,
+    /// which are `None` for slices without samples
+    pub fn percentiles(&self, percentiles: &[f64]) -> Vec<(u64, Vec<Option<u64>>)> {
+        slice_percentiles(&self.heatmap, percentiles)
+    }
+}
+
+// the start time and the given percentiles of each slice of the heatmap
+pub fn slice_percentiles(
+    heatmap: &Heatmap,
+    percentiles: &[f64],
+) -> Vec<(u64, Vec<Option<u64>>)> {
+    let mut series = Vec::new();
+    for slice in heatmap {
+        let start = slice.start();
+        let histogram = slice.histogram();
+        let values = percentiles
+            .iter()
+            .map(|p| histogram.percentile(*p).ok())
+            .collect();
+        series.push((start, values));
+    }
+    series
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn trace() -> Trace {
+        let mut heatmap = Heatmap::configure()
+            .slice_duration(SECOND)
+            .num_slices(10)
+            .precision(2)
+            .start(0)
+            .build()
+            .unwrap();
+        for second in 0..10 {
+            let _ = heatmap.increment_by(second * SECOND, 1_000, 10);
+            let _ = heatmap.increment_by(second * SECOND, 5_000_000, 1);
+        }
+        let layout = Layout::of(&heatmap).unwrap();
+        Trace {
+            heatmap: heatmap,
+            layout: layout,
+            duration: SECOND,
+        }
+    }
+
+    #[test]
+    fn crop_keeps_config() {
+        let mut heatmap = Heatmap::configure()
+            .slice_duration(SECOND / 2)
+            .num_slices(4)
+            .precision(3)
+            .max_value(60 * SECOND)
+            .start(0)
+            .build()
+            .unwrap();
+        let _ = heatmap.increment_by(SECOND, 500, 1);
+        let _ = heatmap.increment_by(SECOND, 30 * SECOND, 1);
+        let mut buffer = Vec::new();
+        write(&heatmap, &mut buffer).unwrap();
+        let (heatmap, layout, duration) = read(&buffer[..]).unwrap();
+        let trace = Trace {
+            heatmap: heatmap,
+            layout: layout,
+            duration: duration,
+        };
+
+        // a precision of 2 would place 500 in the bucket of 502, and the
+        // default max value would drop 30s
+        let trace = trace.crop_time(1, 2);
+        assert_eq!(trace.slices(), 2);
+        assert_eq!(Layout::of(&trace.heatmap), Some(layout));
+        let series = trace.percentiles(&[0.0, 100.0]);
+        assert_eq!(series[0].0, SECOND);
+        assert_eq!(series[0].1[0], Some(500));
+        assert_eq!(series[0].1[1].map(|v| v / SECOND), Some(30));
+        assert_eq!(series[1].0, SECOND + SECOND / 2);
+    }
+
+    #[test]
+    fn malformed() {
+        let inputs: Vec<&[u8]> = vec![
+            b"",
+            b"2 0 1000000000",
+            b"2 0 1000000000 1000000000 2 x\n",
+            b"0 0 1000000000 1000000000 2 0\n",
+            b"2 0 1000000000 1000000000 0 0\n",
+            b"2 0 1000000000 1000000000 2 0\n0 100\n",
+            b"2 0 1000000000 1000000000 2 0\n5000000000 100 1\n",
+            b"\x89PNG\r\n",
+        ];
+        for input in inputs {
+            let error = read(input).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let path = env::temp_dir().join(format!("tic_empty_{}.txt", process::id()));
+        File::create(&path).unwrap();
+        let error = Trace::load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(format!("{}", error), "invalid trace at line 1: empty trace");
+    }
+
+    #[test]
+    fn cropping() {
+        let trace = trace().crop_time(2, 5);
+        assert_eq!(trace.slices(), 3);
+        let series = trace.percentiles(&[0.0, 100.0]);
+        assert_eq!(series.len(), 3);
+        assert_eq!(series[0].0, 2 * SECOND);
+        assert!(series[0].1[1].unwrap() >= 5_000_000);
+
+        let trace = trace.crop_values(0, 1_000_000);
+        let series = trace.percentiles(&[100.0]);
+        assert!(series.iter().all(|s| s.1[0].unwrap() < 1_000_000));
+    }
+
+    #[test]
+    fn layout() {
+        let layouts = [(1, 100), (2, 1_000_000_000), (3, 60 * SECOND), (4, 10)];
+        for &(precision, max_value) in &layouts {
+            let heatmap = Heatmap::configure()
+                .slice_duration(SECOND)
+                .num_slices(2)
+                .precision(precision)
+                .max_value(max_value)
+                .build()
+                .unwrap();
+            let layout = Layout::of(&heatmap).unwrap();
+            assert_eq!(layout.max_value, max_value);
+            // a small max value fits in the linear buckets of any precision
+            if max_value > 10 {
+                assert_eq!(layout.precision, precision);
+            }
+        }
+    }
+
+    #[test]
+    fn write_trace() {
+        let mut heatmap = Heatmap::configure()
+            .slice_duration(SECOND)
+            .num_slices(2)
+            .precision(3)
+            .max_value(60 * SECOND)
+            .start(SECOND)
+            .build()
+            .unwrap();
+        let _ = heatmap.increment_by(2 * SECOND, 500, 3);
+        let mut output = Vec::new();
+        write(&heatmap, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "3 0 60000000000 1000000000 2 1000000000\n2000000000 500 3\n"
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("tic_trace_{}.txt", process::id()));
+        let original = trace();
+        write(&original.heatmap, &mut File::create(&path).unwrap()).unwrap();
+        let loaded = Trace::load(&path).unwrap();
+        assert_eq!(loaded.slices(), original.slices());
+        // the file holds the value of each bucket, which the heatmap may place
+        // in the next bucket on load, so values are kept to within 1%
+        let loaded = loaded.percentiles(&[50.0]);
+        let original = original.percentiles(&[50.0]);
+        assert_eq!(loaded.len(), original.len());
+        for (loaded, original) in loaded.iter().zip(&original) {
+            assert_eq!(loaded.0, original.0);
+            let (loaded, original) = (loaded.1[0].unwrap(), original.1[0].unwrap());
+            assert!(loaded >= original && loaded - original <= original / 100);
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert!(Trace::load(&path).is_err());
+    }
+}